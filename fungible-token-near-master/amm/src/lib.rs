@@ -0,0 +1,763 @@
+/*!
+Constant-product (x * y = k) pool between MUTE and another NEP-141 token.
+NOTES:
+  - Tokens come in through `ft_transfer_call` with a JSON `msg`:
+    `{"action": "deposit"}` credits the sender's deposit in the pool,
+    `{"action": "swap", "min_amount_out": "100"}` swaps and sends the output back to the sender.
+  - Tokens may charge a fee on transfer, so the pool doesn't trust the `amount` passed to
+    `ft_on_transfer`. It reads its own balance from the token and credits the increase over the
+    balance it already accounts for, capped by `amount`.
+  - LP shares are a NEP-141 token implemented by this contract. Accounts register with
+    `storage_deposit` before sending tokens to the pool, which also pays for their deposit record.
+  - The pool itself has to be registered with both tokens.
+*/
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_contract_standards::fungible_token::events::{FtBurn, FtMint};
+use near_contract_standards::fungible_token::metadata::{
+    FungibleTokenMetadata, FungibleTokenMetadataProvider,
+};
+use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+use near_contract_standards::fungible_token::FungibleToken;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LazyOption, LookupMap};
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas,
+    PanicOnDefault, Promise, PromiseError, PromiseOrValue, PromiseResult, StorageUsage, ONE_YOCTO,
+};
+
+mod math;
+
+use math::{mul_div, mul_div_ceil, sqrt_mul};
+
+const FEE_DIVISOR: u128 = 10_000;
+/// Highest swap fee, 10%.
+pub const MAX_FEE_BPS: u16 = 1_000;
+
+const GAS_FOR_FT_BALANCE_OF: Gas = Gas(5_000_000_000_000);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(15_000_000_000_000);
+const GAS_FOR_ON_PAYOUT: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_ON_TRANSFER_RECEIVED: Gas =
+    Gas(GAS_FOR_FT_TRANSFER.0 + GAS_FOR_ON_PAYOUT.0 + 10_000_000_000_000);
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Shares,
+    Metadata,
+    Deposits,
+}
+
+/// Action encoded in the `msg` of `ft_transfer_call`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransferAction {
+    Deposit,
+    Swap { min_amount_out: U128 },
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PoolView {
+    pub token_ids: Vec<AccountId>,
+    pub reserves: [U128; 2],
+    pub known_balances: [U128; 2],
+    pub total_shares: U128,
+    pub fee_bps: u16,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    shares: FungibleToken,
+    metadata: LazyOption<FungibleTokenMetadata>,
+    token_ids: Vec<AccountId>,
+    reserves: [Balance; 2],
+    /// Balance the pool holds in each token according to its own books: reserves, deposits and
+    /// payouts in flight. Anything above it was not received through `ft_on_transfer`.
+    known_balances: [Balance; 2],
+    deposits: LookupMap<AccountId, [Balance; 2]>,
+    fee_bps: u16,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Creates an empty pool for `token_ids`, charging `fee_bps` on swaps. `metadata` describes
+    /// the LP shares.
+    #[init]
+    pub fn new(token_ids: Vec<AccountId>, fee_bps: u16, metadata: FungibleTokenMetadata) -> Self {
+        require!(!env::state_exists(), "Already initialized");
+        require!(
+            token_ids.len() == 2 && token_ids[0] != token_ids[1],
+            "The pool needs two different tokens"
+        );
+        require!(
+            fee_bps <= MAX_FEE_BPS,
+            format!("The swap fee can't exceed {} bps", MAX_FEE_BPS)
+        );
+        metadata.assert_valid();
+        let mut this = Self {
+            shares: FungibleToken::new(StorageKey::Shares),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            token_ids,
+            reserves: [0; 2],
+            known_balances: [0; 2],
+            deposits: LookupMap::new(StorageKey::Deposits),
+            fee_bps,
+        };
+        this.shares.account_storage_usage += this.measure_deposit_storage_usage();
+        this
+    }
+
+    pub fn get_pool(&self) -> PoolView {
+        PoolView {
+            token_ids: self.token_ids.clone(),
+            reserves: self.reserves.map(U128),
+            known_balances: self.known_balances.map(U128),
+            total_shares: self.shares.total_supply.into(),
+            fee_bps: self.fee_bps,
+        }
+    }
+
+    pub fn get_deposits(&self, account_id: AccountId) -> [U128; 2] {
+        self.deposits.get(&account_id).unwrap_or_default().map(U128)
+    }
+
+    /// Amount of the other token a swap of `amount_in` would return now.
+    pub fn get_return(&self, token_in: AccountId, amount_in: U128) -> U128 {
+        let index_in = self.token_index(&token_in);
+        self.amount_out(index_in, amount_in.0).into()
+    }
+
+    /// Moves `amounts` from the predecessor's deposits into the pool at the current ratio and
+    /// mints LP shares. Only the part matching the ratio is used, the rest stays deposited.
+    #[payable]
+    pub fn add_liquidity(&mut self, amounts: [U128; 2], min_shares: U128) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let total_shares = self.shares.total_supply;
+        let (shares, used) = if total_shares == 0 {
+            (sqrt_mul(amounts[0].0, amounts[1].0), amounts.map(|a| a.0))
+        } else {
+            let shares = std::cmp::min(
+                mul_div(amounts[0].0, total_shares, self.reserves[0]),
+                mul_div(amounts[1].0, total_shares, self.reserves[1]),
+            );
+            let used = [0, 1].map(|i| mul_div_ceil(shares, self.reserves[i], total_shares));
+            (shares, used)
+        };
+        require!(shares > 0, "The amounts are too small to mint shares");
+        require!(
+            shares >= min_shares.0,
+            format!(
+                "Slippage error: {} shares minted, {} expected",
+                shares, min_shares.0
+            )
+        );
+
+        let mut deposits = self.internal_deposits(&account_id);
+        for i in 0..2 {
+            require!(deposits[i] >= used[i], "Not enough deposited");
+            deposits[i] -= used[i];
+            self.reserves[i] += used[i];
+        }
+        self.deposits.insert(&account_id, &deposits);
+        self.shares.internal_deposit(&account_id, shares);
+        FtMint {
+            owner_id: &account_id,
+            amount: &U128(shares),
+            memo: Some("Liquidity added"),
+        }
+        .emit();
+        shares.into()
+    }
+
+    /// Burns LP shares and credits the predecessor's deposits with their part of the reserves.
+    #[payable]
+    pub fn remove_liquidity(&mut self, shares: U128, min_amounts: [U128; 2]) -> [U128; 2] {
+        assert_one_yocto();
+        require!(shares.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let total_shares = self.shares.total_supply;
+        let amounts = [0, 1].map(|i| mul_div(shares.0, self.reserves[i], total_shares));
+        for i in 0..2 {
+            require!(
+                amounts[i] >= min_amounts[i].0,
+                format!(
+                    "Slippage error: {} of {} returned, {} expected",
+                    amounts[i], self.token_ids[i], min_amounts[i].0
+                )
+            );
+        }
+
+        self.shares.internal_withdraw(&account_id, shares.0);
+        let mut deposits = self.internal_deposits(&account_id);
+        for i in 0..2 {
+            deposits[i] += amounts[i];
+            self.reserves[i] -= amounts[i];
+        }
+        self.deposits.insert(&account_id, &deposits);
+        FtBurn {
+            owner_id: &account_id,
+            amount: &shares,
+            memo: Some("Liquidity removed"),
+        }
+        .emit();
+        amounts.map(U128)
+    }
+
+    /// Swaps deposited `token_in` for the other token, which is credited to the deposits.
+    #[payable]
+    pub fn swap(&mut self, token_in: AccountId, amount_in: U128, min_amount_out: U128) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let index_in = self.token_index(&token_in);
+        let mut deposits = self.internal_deposits(&account_id);
+        require!(deposits[index_in] >= amount_in.0, "Not enough deposited");
+        deposits[index_in] -= amount_in.0;
+        let amount_out = self.internal_swap(index_in, amount_in.0, min_amount_out.0);
+        deposits[1 - index_in] += amount_out;
+        self.deposits.insert(&account_id, &deposits);
+        amount_out.into()
+    }
+
+    /// Sends deposited tokens back to the predecessor, all of them if `amount` is omitted.
+    #[payable]
+    pub fn withdraw(&mut self, token_id: AccountId, amount: Option<U128>) -> Promise {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let index = self.token_index(&token_id);
+        let mut deposits = self.internal_deposits(&account_id);
+        let amount = amount.map_or(deposits[index], |amount| amount.0);
+        require!(amount > 0, "The amount should be a positive number");
+        require!(deposits[index] >= amount, "Not enough deposited");
+        deposits[index] -= amount;
+        self.deposits.insert(&account_id, &deposits);
+        self.internal_payout(&account_id, index, amount)
+    }
+
+    /// Credits what the pool actually received for a transfer, measured with the balance read
+    /// in the previous promise, and applies the action. Returns the unused amount, always 0:
+    /// failures panic, so the token refunds the whole transfer.
+    #[private]
+    pub fn on_transfer_received(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+        action: TransferAction,
+        #[callback_result] balance: Result<U128, PromiseError>,
+    ) -> U128 {
+        let index = self.token_index(&token_id);
+        let balance = balance
+            .unwrap_or_else(|_| env::panic_str("Failed to read the balance of the pool"))
+            .0;
+        let received = std::cmp::min(balance.saturating_sub(self.known_balances[index]), amount.0);
+        require!(received > 0, "Nothing was received");
+        self.known_balances[index] += received;
+        if received < amount.0 {
+            log!(
+                "Received {} of {} {} from @{}",
+                received,
+                amount.0,
+                token_id,
+                sender_id
+            );
+        }
+
+        match action {
+            TransferAction::Deposit => {
+                let mut deposits = self.internal_deposits(&sender_id);
+                deposits[index] += received;
+                self.deposits.insert(&sender_id, &deposits);
+            }
+            TransferAction::Swap { min_amount_out } => {
+                let amount_out = self.internal_swap(index, received, min_amount_out.0);
+                self.internal_payout(&sender_id, 1 - index, amount_out);
+            }
+        }
+        U128(0)
+    }
+
+    /// Returns a failed payout to the account's deposits. Returns the amount paid out.
+    #[private]
+    pub fn on_payout(&mut self, account_id: AccountId, index: u8, amount: U128) -> U128 {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return amount;
+        }
+        let index = index as usize;
+        self.known_balances[index] += amount.0;
+        if let Some(mut deposits) = self.deposits.get(&account_id) {
+            deposits[index] += amount.0;
+            self.deposits.insert(&account_id, &deposits);
+            log!("Payout of {} to @{} failed", amount.0, account_id);
+        } else {
+            // The account unregistered in the meantime, the tokens go to the liquidity providers.
+            self.reserves[index] += amount.0;
+            log!(
+                "Payout of {} to @{} failed, added to the reserves",
+                amount.0,
+                account_id
+            );
+        }
+        U128(0)
+    }
+}
+
+impl Contract {
+    fn measure_deposit_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.deposits.insert(&tmp_account_id, &[0; 2]);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.deposits.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn token_index(&self, token_id: &AccountId) -> usize {
+        self.token_ids
+            .iter()
+            .position(|id| id == token_id)
+            .unwrap_or_else(|| env::panic_str(&format!("Token {} is not in the pool", token_id)))
+    }
+
+    fn internal_deposits(&self, account_id: &AccountId) -> [Balance; 2] {
+        self.deposits.get(account_id).unwrap_or_else(|| {
+            env::panic_str(&format!("The account {} is not registered", account_id))
+        })
+    }
+
+    fn amount_out(&self, index_in: usize, amount_in: Balance) -> Balance {
+        let (reserve_in, reserve_out) = (self.reserves[index_in], self.reserves[1 - index_in]);
+        require!(
+            reserve_in > 0 && reserve_out > 0,
+            "The pool has no liquidity"
+        );
+        let amount_in_with_fee = amount_in * (FEE_DIVISOR - self.fee_bps as u128);
+        mul_div(
+            amount_in_with_fee,
+            reserve_out,
+            reserve_in * FEE_DIVISOR + amount_in_with_fee,
+        )
+    }
+
+    /// Adds `amount_in` to the reserves and takes out the other token, keeping `x * y` from
+    /// decreasing. Panics if the output is below `min_amount_out`.
+    fn internal_swap(
+        &mut self,
+        index_in: usize,
+        amount_in: Balance,
+        min_amount_out: Balance,
+    ) -> Balance {
+        require!(amount_in > 0, "The amount should be a positive number");
+        let amount_out = self.amount_out(index_in, amount_in);
+        require!(amount_out > 0, "The amount is too small to swap");
+        require!(
+            amount_out >= min_amount_out,
+            format!(
+                "Slippage error: {} returned, {} expected",
+                amount_out, min_amount_out
+            )
+        );
+        self.reserves[index_in] += amount_in;
+        self.reserves[1 - index_in] -= amount_out;
+        log!(
+            "Swapped {} {} for {} {}",
+            amount_in,
+            self.token_ids[index_in],
+            amount_out,
+            self.token_ids[1 - index_in]
+        );
+        amount_out
+    }
+
+    /// Sends `amount` of the token at `index`, returning it to the deposits if the transfer fails.
+    fn internal_payout(
+        &mut self,
+        account_id: &AccountId,
+        index: usize,
+        amount: Balance,
+    ) -> Promise {
+        self.known_balances[index] -= amount;
+        ext_ft_core::ext(self.token_ids[index].clone())
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(account_id.clone(), amount.into(), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_PAYOUT)
+                    .on_payout(account_id.clone(), index as u8, amount.into()),
+            )
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenReceiver for Contract {
+    /// Reads the pool's balance in the token before crediting anything, see
+    /// `on_transfer_received`.
+    fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let token_id = env::predecessor_account_id();
+        self.token_index(&token_id);
+        require!(
+            self.deposits.contains_key(&sender_id),
+            format!("The account {} is not registered", sender_id)
+        );
+        let action: TransferAction = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("Invalid transfer message"));
+        ext_ft_core::ext(token_id.clone())
+            .with_static_gas(GAS_FOR_FT_BALANCE_OF)
+            .ft_balance_of(env::current_account_id())
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_TRANSFER_RECEIVED)
+                    .on_transfer_received(token_id, sender_id, amount, action),
+            )
+            .into()
+    }
+}
+
+near_contract_standards::impl_fungible_token_core!(Contract, shares);
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    /// Registers the account for LP shares and creates its deposit record.
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let storage_balance = self
+            .shares
+            .storage_deposit(Some(account_id.clone()), registration_only);
+        if !self.deposits.contains_key(&account_id) {
+            self.deposits.insert(&account_id, &[0; 2]);
+        }
+        storage_balance
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.shares.storage_withdraw(amount)
+    }
+
+    /// Deposits have to be withdrawn first. With `force`, remaining LP shares are burned and
+    /// their part of the reserves goes to the other liquidity providers.
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        let account_id = env::predecessor_account_id();
+        if let Some(deposits) = self.deposits.get(&account_id) {
+            require!(
+                deposits == [0; 2],
+                "The account has deposits, withdraw them first"
+            );
+        }
+        if let Some((account_id, shares)) = self.shares.internal_storage_unregister(force) {
+            self.deposits.remove(&account_id);
+            if shares > 0 {
+                FtBurn {
+                    owner_id: &account_id,
+                    amount: &U128(shares),
+                    memo: Some("Account closed"),
+                }
+                .emit();
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        self.shares.storage_balance_bounds()
+    }
+
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        self.shares.storage_balance_of(account_id)
+    }
+}
+
+#[near_bindgen]
+impl FungibleTokenMetadataProvider for Contract {
+    fn ft_metadata(&self) -> FungibleTokenMetadata {
+        self.metadata.get().unwrap()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_contract_standards::fungible_token::metadata::FT_METADATA_SPEC;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    const MUTE: usize = 0;
+    const WNEAR: usize = 1;
+
+    fn token(index: usize) -> AccountId {
+        ["mute.near", "wrap.near"][index].parse().unwrap()
+    }
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        ctx.current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new(
+            vec![token(MUTE), token(WNEAR)],
+            30,
+            FungibleTokenMetadata {
+                spec: FT_METADATA_SPEC.to_string(),
+                name: "MUTE-wNEAR LP".to_string(),
+                symbol: "MUTE-LP".to_string(),
+                icon: None,
+                reference: None,
+                reference_hash: None,
+                decimals: 24,
+            },
+        );
+        for i in 1..3 {
+            testing_env!(ctx
+                .storage_usage(env::storage_usage())
+                .attached_deposit(contract.storage_balance_bounds().min.0)
+                .predecessor_account_id(accounts(i))
+                .build());
+            contract.storage_deposit(None, None);
+        }
+        contract
+    }
+
+    /// `ft_transfer_call` of `amount` from `sender`, after which the pool's balance in the token
+    /// is `balance`.
+    fn transfer_call(
+        ctx: &mut VMContextBuilder,
+        contract: &mut Contract,
+        index: usize,
+        sender: AccountId,
+        amount: Balance,
+        balance: Balance,
+        msg: &str,
+    ) -> U128 {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .predecessor_account_id(token(index))
+            .build());
+        contract.ft_on_transfer(sender.clone(), amount.into(), msg.to_string());
+        let action = near_sdk::serde_json::from_str(msg).unwrap();
+        testing_env!(
+            ctx.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Successful(
+                near_sdk::serde_json::to_vec(&U128(balance)).unwrap()
+            )],
+        );
+        contract.on_transfer_received(
+            token(index),
+            sender,
+            amount.into(),
+            action,
+            Ok(U128(balance)),
+        )
+    }
+
+    fn call_as(ctx: &mut VMContextBuilder, account_id: AccountId) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(account_id)
+            .build());
+    }
+
+    fn seed(ctx: &mut VMContextBuilder, contract: &mut Contract) {
+        transfer_call(
+            ctx,
+            contract,
+            MUTE,
+            accounts(1),
+            1_000_000,
+            1_000_000,
+            r#"{"action": "deposit"}"#,
+        );
+        transfer_call(
+            ctx,
+            contract,
+            WNEAR,
+            accounts(1),
+            4_000_000,
+            4_000_000,
+            r#"{"action": "deposit"}"#,
+        );
+        call_as(ctx, accounts(1));
+        contract.add_liquidity([U128(1_000_000), U128(4_000_000)], U128(0));
+    }
+
+    #[test]
+    fn test_add_and_remove_liquidity() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        seed(ctx, &mut contract);
+        assert_eq!(contract.ft_balance_of(accounts(1)), U128(2_000_000));
+
+        transfer_call(
+            ctx,
+            &mut contract,
+            MUTE,
+            accounts(2),
+            1_000,
+            1_001_000,
+            r#"{"action": "deposit"}"#,
+        );
+        transfer_call(
+            ctx,
+            &mut contract,
+            WNEAR,
+            accounts(2),
+            5_000,
+            4_005_000,
+            r#"{"action": "deposit"}"#,
+        );
+        call_as(ctx, accounts(2));
+        // Only the amounts matching the 1:4 ratio are used.
+        assert_eq!(
+            contract.add_liquidity([U128(1_000), U128(5_000)], U128(2_000)),
+            U128(2_000)
+        );
+        assert_eq!(contract.get_deposits(accounts(2)), [U128(0), U128(1_000)]);
+
+        assert_eq!(
+            contract.remove_liquidity(U128(2_000), [U128(1_000), U128(4_000)]),
+            [U128(1_000), U128(4_000)]
+        );
+        assert_eq!(
+            contract.get_deposits(accounts(2)),
+            [U128(1_000), U128(5_000)]
+        );
+        assert_eq!(contract.ft_total_supply(), U128(2_000_000));
+        assert_eq!(
+            contract.get_pool().reserves,
+            [U128(1_000_000), U128(4_000_000)]
+        );
+    }
+
+    #[test]
+    fn test_fee_on_transfer_credits_received_amount() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        seed(ctx, &mut contract);
+
+        // The token passes 1_000 but only 900 arrived.
+        transfer_call(
+            ctx,
+            &mut contract,
+            MUTE,
+            accounts(2),
+            1_000,
+            1_000_900,
+            r#"{"action": "deposit"}"#,
+        );
+        assert_eq!(contract.get_deposits(accounts(2))[MUTE], U128(900));
+        assert_eq!(contract.get_pool().known_balances[MUTE], U128(1_000_900));
+
+        // Tokens sent without `ft_transfer_call` are not credited to the next depositor.
+        transfer_call(
+            ctx,
+            &mut contract,
+            MUTE,
+            accounts(2),
+            100,
+            1_005_000,
+            r#"{"action": "deposit"}"#,
+        );
+        assert_eq!(contract.get_deposits(accounts(2))[MUTE], U128(1_000));
+    }
+
+    #[test]
+    fn test_swap_with_measured_input() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        seed(ctx, &mut contract);
+
+        // 10_000 sent, 9_000 received: the output is computed from 9_000.
+        let expected = contract.get_return(token(MUTE), U128(9_000));
+        assert_eq!(expected, U128(35_572));
+        transfer_call(
+            ctx,
+            &mut contract,
+            MUTE,
+            accounts(2),
+            10_000,
+            1_009_000,
+            r#"{"action": "swap", "min_amount_out": "35000"}"#,
+        );
+        let pool = contract.get_pool();
+        assert_eq!(pool.reserves, [U128(1_009_000), U128(4_000_000 - 35_572)]);
+        assert_eq!(pool.known_balances, pool.reserves);
+
+        // The output payout failed, so it is credited to the deposits.
+        testing_env!(
+            ctx.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        contract.on_payout(accounts(2), WNEAR as u8, expected);
+        assert_eq!(contract.get_deposits(accounts(2))[WNEAR], expected);
+        assert_eq!(contract.get_pool().known_balances[WNEAR], U128(4_000_000));
+    }
+
+    #[test]
+    fn test_constant_product_does_not_decrease() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        seed(ctx, &mut contract);
+        transfer_call(
+            ctx,
+            &mut contract,
+            WNEAR,
+            accounts(2),
+            50_000,
+            4_050_000,
+            r#"{"action": "deposit"}"#,
+        );
+        call_as(ctx, accounts(2));
+        let k = contract.reserves[0] * contract.reserves[1];
+        for _ in 0..5 {
+            let out = contract.swap(token(WNEAR), U128(10_000), U128(0));
+            contract.swap(token(MUTE), out, U128(0));
+            assert!(contract.reserves[0] * contract.reserves[1] >= k);
+        }
+        // Round trips lose the swap fee.
+        assert!(contract.get_deposits(accounts(2))[WNEAR].0 < 50_000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Slippage error: 35572 returned, 36000 expected")]
+    fn test_swap_slippage() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        seed(ctx, &mut contract);
+        transfer_call(
+            ctx,
+            &mut contract,
+            MUTE,
+            accounts(2),
+            10_000,
+            1_009_000,
+            r#"{"action": "swap", "min_amount_out": "36000"}"#,
+        );
+    }
+}
@@ -0,0 +1,22 @@
+//! Wide arithmetic for pool and share calculations.
+#![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+
+uint::construct_uint! {
+    pub struct U256(4);
+}
+
+/// `a * b / c` rounded down, without intermediate overflow. Panics if the result doesn't fit.
+pub fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+/// `a * b / c` rounded up.
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> u128 {
+    let c = U256::from(c);
+    ((U256::from(a) * U256::from(b) + c - 1) / c).as_u128()
+}
+
+/// Integer square root of `a * b`, rounded down.
+pub fn sqrt_mul(a: u128, b: u128) -> u128 {
+    (U256::from(a) * U256::from(b)).integer_sqrt().as_u128()
+}
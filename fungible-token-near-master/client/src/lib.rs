@@ -0,0 +1,102 @@
+/*!
+Client-side helpers for the MUTE token.
+
+Token methods panic with a stable error code in front of the message, e.g.
+`E012: The account is not registered @bob.near`. Nodes and client libraries wrap the message in
+their own error text, `ContractError::from_panic_message` finds it there and maps it back to
+`MuteError`, along with the details that follow the message.
+*/
+use std::fmt;
+
+pub use fungible_token::errors::{ErrorCodeView, MuteError};
+
+/// A token method failure, as reported to a client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContractError {
+    pub error: MuteError,
+    /// What follows the message, such as the account involved or the end of a cooldown.
+    pub detail: Option<String>,
+}
+
+impl ContractError {
+    /// Finds the error in the text of a failed call, e.g.
+    /// `Smart contract panicked: E005: The account is in cooldown until 1060000000000`.
+    /// Returns `None` for failures without a MUTE error code.
+    pub fn from_panic_message(message: &str) -> Option<Self> {
+        let error = MuteError::from_panic_message(message)?;
+        let text = error.to_string();
+        let start = message.find(&text)? + text.len();
+        let detail = message[start..]
+            .split(['"', '\'', '\\', ')', '}', ']', '\n'])
+            .next()
+            .map(str::trim)
+            .filter(|detail| !detail.is_empty())
+            .map(str::to_string);
+        Some(Self { error, detail })
+    }
+}
+
+impl fmt::Display for ContractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.detail {
+            Some(detail) => write!(f, "{} {}", self.error, detail),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for ContractError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_with_detail() {
+        let message = r#"Action #0: ExecutionError("Smart contract panicked: E012: The account is not registered @bob.near")"#;
+        let error = ContractError::from_panic_message(message).unwrap();
+        assert_eq!(
+            error,
+            ContractError {
+                error: MuteError::AccountNotRegistered,
+                detail: Some("@bob.near".to_string()),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "E012: The account is not registered @bob.near"
+        );
+    }
+
+    #[test]
+    fn test_error_without_detail() {
+        let message = "Smart contract panicked: E019: Can't unregister the account with the positive balance without force";
+        assert_eq!(
+            ContractError::from_panic_message(message),
+            Some(ContractError {
+                error: MuteError::PositiveBalanceWithoutForce,
+                detail: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_every_code_maps_back() {
+        for error in MuteError::ALL {
+            let message = format!("Smart contract panicked: {} 60", error);
+            let parsed = ContractError::from_panic_message(&message).unwrap();
+            assert_eq!(parsed.error, error);
+            assert_eq!(parsed.detail.as_deref(), Some("60"));
+        }
+    }
+
+    #[test]
+    fn test_failure_without_code() {
+        assert_eq!(
+            ContractError::from_panic_message(
+                "Smart contract panicked: The amount should be a positive number"
+            ),
+            None
+        );
+    }
+}
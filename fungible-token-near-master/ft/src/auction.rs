@@ -0,0 +1,459 @@
+//! Dutch auction of treasury tranches. The price of a whole token falls linearly from the start
+//! price to the end price while bidders commit NEAR.
+//!
+//! The auction sells out once the commitments buy the whole tranche at the current price, and
+//! commitments beyond that are refunded right away. Everyone pays the same clearing price: the
+//! price when the auction sold out, or the end price. Bidders then claim their tokens and the
+//! refund of what they committed above the clearing price. Unsold tokens go back to the owner.
+use crate::events::MuteEvent;
+use crate::launch::NANOS_PER_SECOND;
+use crate::math::{mul_div, mul_div_ceil};
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{IntoStorageKey, Promise, Timestamp};
+
+/// Longest auction, 30 days.
+pub const MAX_AUCTION_DURATION_SECS: u64 = 30 * 24 * 3_600;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionConfig {
+    /// Prices of a whole token in yoctoNEAR.
+    pub start_price: U128,
+    pub end_price: U128,
+    pub start_at: U64,
+    pub end_at: U64,
+    /// Tokens on sale.
+    pub tranche: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct DutchAuction {
+    pub config: Option<AuctionConfig>,
+    /// Units in a whole token.
+    pub unit: Balance,
+    pub total_committed: Balance,
+    /// Commitments not claimed yet.
+    pub pending: Balance,
+    pub clearing_price: Option<Balance>,
+    /// Tokens taken from the owner for the auction and not claimed yet.
+    pub held: Balance,
+    pub proceeds: Balance,
+    pub commitments: LookupMap<AccountId, Balance>,
+}
+
+impl DutchAuction {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            config: None,
+            unit: 1,
+            total_committed: 0,
+            pending: 0,
+            clearing_price: None,
+            held: 0,
+            proceeds: 0,
+            commitments: LookupMap::new(prefix),
+        }
+    }
+
+    /// Storage taken by one commitment, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.commitments.insert(&tmp_account_id, &0);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.commitments.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn config(&self) -> &AuctionConfig {
+        self.config
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("No auction was started"))
+    }
+
+    /// Price of a whole token at `now`.
+    pub fn price_at(&self, now: Timestamp) -> Balance {
+        let config = self.config();
+        let (start_at, end_at) = (config.start_at.0, config.end_at.0);
+        let now = now.clamp(start_at, end_at);
+        config.start_price.0
+            - mul_div(
+                config.start_price.0 - config.end_price.0,
+                (now - start_at) as u128,
+                (end_at - start_at) as u128,
+            )
+    }
+
+    /// Commitments that buy the whole tranche at `price`.
+    fn sell_out_amount(&self, price: Balance) -> Balance {
+        mul_div_ceil(self.config().tranche.0, price, self.unit)
+    }
+
+    pub fn sold_out(&self, now: Timestamp) -> bool {
+        self.total_committed >= self.sell_out_amount(self.price_at(now))
+    }
+
+    /// Tokens a commitment buys at the clearing price and the NEAR refunded.
+    pub fn claim_of(&self, committed: Balance) -> (Balance, Balance) {
+        let clearing_price = self
+            .clearing_price
+            .unwrap_or_else(|| env::panic_str("The auction is not finalized"));
+        let tokens = mul_div(committed, self.unit, clearing_price);
+        let cost = mul_div_ceil(tokens, clearing_price, self.unit);
+        (tokens, committed - cost)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionInfo {
+    pub config: Option<AuctionConfig>,
+    /// Current price of a whole token, in yoctoNEAR.
+    pub price: Option<U128>,
+    pub total_committed: U128,
+    pub sold_out: bool,
+    pub clearing_price: Option<U128>,
+    pub proceeds: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct AuctionCommitment {
+    pub committed: U128,
+    /// Known once the auction is finalized.
+    pub tokens: Option<U128>,
+    pub refund: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn auction_info(&self) -> AuctionInfo {
+        let now = env::block_timestamp();
+        let started = self.auction.config.is_some();
+        AuctionInfo {
+            config: self.auction.config.clone(),
+            price: started.then(|| self.auction.price_at(now).into()),
+            total_committed: self.auction.total_committed.into(),
+            sold_out: started && self.auction.sold_out(now),
+            clearing_price: self.auction.clearing_price.map(U128),
+            proceeds: self.auction.proceeds.into(),
+        }
+    }
+
+    pub fn auction_commitment_of(&self, account_id: AccountId) -> Option<AuctionCommitment> {
+        let committed = self.auction.commitments.get(&account_id)?;
+        let claim = self
+            .auction
+            .clearing_price
+            .map(|_| self.auction.claim_of(committed));
+        Some(AuctionCommitment {
+            committed: committed.into(),
+            tokens: claim.map(|(tokens, _)| tokens.into()),
+            refund: claim.map(|(_, refund)| refund.into()),
+        })
+    }
+
+    /// Takes `tranche` tokens from the owner and auctions them for `duration_secs` from
+    /// `start_at`, or from now. The previous auction must be finalized and fully claimed.
+    #[payable]
+    pub fn start_auction(
+        &mut self,
+        start_price: U128,
+        end_price: U128,
+        duration_secs: u64,
+        tranche: U128,
+        start_at: Option<U64>,
+    ) -> AuctionConfig {
+        self.assert_owner();
+        require!(
+            self.auction.config.is_none()
+                || (self.auction.clearing_price.is_some() && self.auction.pending == 0),
+            "The previous auction is not finalized and claimed"
+        );
+        require!(
+            start_price.0 >= end_price.0 && end_price.0 > 0,
+            "The start price must be at least the end price, which must be positive"
+        );
+        require!(
+            duration_secs > 0 && duration_secs <= MAX_AUCTION_DURATION_SECS,
+            format!(
+                "Auction duration must be between 1 and {} seconds",
+                MAX_AUCTION_DURATION_SECS
+            )
+        );
+        require!(tranche.0 > 0, "The tranche should be positive");
+        let start_at = start_at.map_or(env::block_timestamp(), |start_at| start_at.0);
+        require!(
+            start_at >= env::block_timestamp(),
+            "The auction can't start in the past"
+        );
+        self.internal_debit(&self.owner_id.clone(), tranche.0);
+
+        let config = AuctionConfig {
+            start_price,
+            end_price,
+            start_at: start_at.into(),
+            end_at: (start_at + duration_secs * NANOS_PER_SECOND).into(),
+            tranche,
+        };
+        self.auction.config = Some(config.clone());
+        self.auction.unit = self.token_unit();
+        self.auction.total_committed = 0;
+        self.auction.clearing_price = None;
+        self.auction.held = tranche.0;
+        MuteEvent::AuctionStarted { config: &config }.emit();
+        config
+    }
+
+    /// Commits the attached NEAR at the current price. The part beyond what buys the rest of
+    /// the tranche is refunded.
+    #[payable]
+    pub fn commit(&mut self) -> U128 {
+        let now = env::block_timestamp();
+        let config = self.auction.config();
+        require!(
+            self.auction.clearing_price.is_none()
+                && now >= config.start_at.0
+                && now < config.end_at.0,
+            "The auction is not running"
+        );
+        let account_id = env::predecessor_account_id();
+        self.assert_registered(&account_id);
+        let remaining = self
+            .auction
+            .sell_out_amount(self.auction.price_at(now))
+            .saturating_sub(self.auction.total_committed);
+        require!(remaining > 0, "The auction is sold out");
+        let deposit = env::attached_deposit();
+        let amount = std::cmp::min(deposit, remaining);
+        require!(amount > 0, "Requires positive attached deposit");
+
+        let committed = self.auction.commitments.get(&account_id).unwrap_or(0) + amount;
+        self.auction.commitments.insert(&account_id, &committed);
+        self.auction.total_committed += amount;
+        self.auction.pending += amount;
+        MuteEvent::AuctionCommitted {
+            account_id: &account_id,
+            amount: amount.into(),
+        }
+        .emit();
+        if deposit > amount {
+            Promise::new(account_id).transfer(deposit - amount);
+        }
+        committed.into()
+    }
+
+    /// Sets the clearing price once the auction ended or sold out and returns the unsold tokens
+    /// to the owner. Anyone can call it.
+    pub fn finalize_auction(&mut self) -> U128 {
+        let now = env::block_timestamp();
+        let config = self.auction.config();
+        require!(
+            self.auction.clearing_price.is_none(),
+            "The auction is already finalized"
+        );
+        require!(
+            now >= config.end_at.0 || self.auction.sold_out(now),
+            "The auction is still running"
+        );
+        let tranche = config.tranche.0;
+        // At least the price that sells the whole tranche for the commitments, rounded up so
+        // the claims never exceed the tranche.
+        let committed_price =
+            mul_div_ceil(self.auction.total_committed, self.auction.unit, tranche);
+        let clearing_price = std::cmp::max(committed_price, self.auction.price_at(now));
+        let sold = mul_div(
+            self.auction.total_committed,
+            self.auction.unit,
+            clearing_price,
+        );
+
+        self.auction.clearing_price = Some(clearing_price);
+        self.auction.held = sold;
+        if tranche > sold {
+            self.internal_credit(&self.owner_id.clone(), tranche - sold);
+        }
+        MuteEvent::AuctionFinalized {
+            clearing_price: clearing_price.into(),
+            sold: sold.into(),
+        }
+        .emit();
+        clearing_price.into()
+    }
+
+    /// Credits the predecessor's tokens and refunds what it committed above the clearing price.
+    pub fn claim_auction(&mut self) -> U128 {
+        let account_id = env::predecessor_account_id();
+        let committed = self
+            .auction
+            .commitments
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("Nothing to claim"));
+        let (tokens, refund) = self.auction.claim_of(committed);
+        self.auction.commitments.remove(&account_id);
+        self.auction.pending -= committed;
+        self.auction.held -= tokens;
+        self.auction.proceeds += committed - refund;
+        self.internal_credit(&account_id, tokens);
+        if self.auction.pending == 0 && self.auction.held > 0 {
+            // Rounding dust of the last claims.
+            self.internal_credit(&self.owner_id.clone(), self.auction.held);
+            self.auction.held = 0;
+        }
+        MuteEvent::AuctionClaimed {
+            account_id: &account_id,
+            amount: tokens.into(),
+            refund: refund.into(),
+        }
+        .emit();
+        if refund > 0 {
+            Promise::new(account_id).transfer(refund);
+        }
+        tokens.into()
+    }
+
+    /// Sends the auction proceeds collected from claims to the owner.
+    #[payable]
+    pub fn withdraw_auction_proceeds(&mut self) -> Promise {
+        self.assert_owner();
+        let amount = self.auction.proceeds;
+        require!(amount > 0, "No auction proceeds to withdraw");
+        self.auction.proceeds = 0;
+        MuteEvent::AuctionProceedsWithdrawn {
+            account_id: &self.owner_id,
+            amount: amount.into(),
+        }
+        .emit();
+        Promise::new(self.owner_id.clone()).transfer(amount)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    const TOKEN: Balance = 10u128.pow(DEFAULT_DECIMALS as u32);
+    const HOUR: u64 = 3_600;
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        register_user(ctx, &mut contract, 4);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        // 100 tokens from 10 NEAR down to 1 NEAR each, 1 NEAR less every hour.
+        contract.start_auction(
+            U128(10 * ONE_NEAR),
+            U128(ONE_NEAR),
+            9 * HOUR,
+            U128(100 * TOKEN),
+            None,
+        );
+        contract
+    }
+
+    fn at(ctx: &mut VMContextBuilder, account: usize, secs: u64, deposit: Balance) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(account))
+            .attached_deposit(deposit)
+            .block_timestamp(secs * NANOS_PER_SECOND)
+            .build());
+    }
+
+    #[test]
+    fn test_price_falls_linearly() {
+        let ctx = &mut get_context(accounts(1));
+        let contract = setup(ctx);
+        assert_eq!(
+            contract.ft_balance_of(accounts(1)).0,
+            DEFAULT_TOTAL_SUPPLY - 100 * TOKEN
+        );
+        for (secs, price) in [
+            (0, 10),
+            (HOUR, 9),
+            (5 * HOUR, 5),
+            (9 * HOUR, 1),
+            (20 * HOUR, 1),
+        ] {
+            at(ctx, 2, secs, 0);
+            assert_eq!(contract.auction_info().price, Some(U128(price * ONE_NEAR)));
+        }
+    }
+
+    #[test]
+    fn test_sold_out_clearing_price_and_claims() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, HOUR, 300 * ONE_NEAR);
+        contract.commit();
+        // At 5 NEAR the tranche costs 500 NEAR, so only 200 of the 300 are accepted.
+        at(ctx, 4, 5 * HOUR, 300 * ONE_NEAR);
+        assert_eq!(contract.commit(), U128(200 * ONE_NEAR));
+        let info = contract.auction_info();
+        assert!(info.sold_out);
+        assert_eq!(info.total_committed, U128(500 * ONE_NEAR));
+
+        contract.finalize_auction();
+        assert_eq!(
+            contract.auction_info().clearing_price,
+            Some(U128(5 * ONE_NEAR))
+        );
+        // The early bidder committed at a higher price but pays the clearing price too.
+        at(ctx, 2, 6 * HOUR, 0);
+        assert_eq!(
+            contract.auction_commitment_of(accounts(2)),
+            Some(AuctionCommitment {
+                committed: U128(300 * ONE_NEAR),
+                tokens: Some(U128(60 * TOKEN)),
+                refund: Some(U128(0)),
+            })
+        );
+        assert_eq!(contract.claim_auction(), U128(60 * TOKEN));
+        at(ctx, 4, 6 * HOUR, 0);
+        assert_eq!(contract.claim_auction(), U128(40 * TOKEN));
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 40 * TOKEN);
+        assert_eq!(contract.auction_info().proceeds, U128(500 * ONE_NEAR));
+        assert_eq!(contract.auction.held, 0);
+    }
+
+    #[test]
+    fn test_undersubscribed_auction_returns_unsold_tokens() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, 2 * HOUR, 25 * ONE_NEAR + 1);
+        contract.commit();
+        at(ctx, 4, 10 * HOUR, 0);
+        contract.finalize_auction();
+        // Nothing sold out, so everyone pays the end price.
+        assert_eq!(contract.auction_info().clearing_price, Some(U128(ONE_NEAR)));
+        assert_eq!(
+            contract.ft_balance_of(accounts(1)).0,
+            DEFAULT_TOTAL_SUPPLY - 25 * TOKEN
+        );
+        at(ctx, 2, 11 * HOUR, 0);
+        let tokens = contract.claim_auction();
+        assert_eq!(tokens.0, 25 * TOKEN);
+        assert_eq!(contract.auction_info().proceeds, U128(25 * ONE_NEAR));
+        assert_eq!(contract.auction.held, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The auction is still running")]
+    fn test_finalize_before_end() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, 9 * HOUR - 1, 0);
+        contract.finalize_auction();
+    }
+}
@@ -0,0 +1,198 @@
+//! Errors of the core token methods with stable numeric codes.
+//!
+//! A panic message starts with the code, e.g. `E003: The account doesn't have enough balance`,
+//! and may end with details such as the account involved. Codes are never reused or renumbered,
+//! so clients can match on them with `MuteError::from_panic_message` instead of on the text.
+use near_sdk::serde::Serialize;
+use near_sdk::{env, require};
+use std::fmt;
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum MuteError {
+    AlreadyInitialized = 1,
+    MoreGasRequired = 2,
+    InsufficientBalance = 3,
+    TradingNotEnabled = 4,
+    SenderInCooldown = 5,
+    MaxTxExceeded = 6,
+    MaxWalletExceeded = 7,
+    SelfTransferBlocked = 8,
+    ReceiverBlocked = 9,
+    ContractReceiverRequiresCall = 10,
+    RegistrationDepositTooSmall = 11,
+    AccountNotRegistered = 12,
+    OneYoctoRequired = 13,
+    NotOwner = 14,
+    FeeReceiverCantUnregister = 15,
+    AccountHasLockedTokens = 16,
+    AccountHasStake = 17,
+    AccountHasAuctionCommitment = 18,
+    PositiveBalanceWithoutForce = 19,
+    NotInitialized = 20,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ErrorCodeView {
+    pub code: u32,
+    pub error: MuteError,
+    pub message: &'static str,
+}
+
+impl MuteError {
+    pub const ALL: [MuteError; 20] = [
+        MuteError::AlreadyInitialized,
+        MuteError::MoreGasRequired,
+        MuteError::InsufficientBalance,
+        MuteError::TradingNotEnabled,
+        MuteError::SenderInCooldown,
+        MuteError::MaxTxExceeded,
+        MuteError::MaxWalletExceeded,
+        MuteError::SelfTransferBlocked,
+        MuteError::ReceiverBlocked,
+        MuteError::ContractReceiverRequiresCall,
+        MuteError::RegistrationDepositTooSmall,
+        MuteError::AccountNotRegistered,
+        MuteError::OneYoctoRequired,
+        MuteError::NotOwner,
+        MuteError::FeeReceiverCantUnregister,
+        MuteError::AccountHasLockedTokens,
+        MuteError::AccountHasStake,
+        MuteError::AccountHasAuctionCommitment,
+        MuteError::PositiveBalanceWithoutForce,
+        MuteError::NotInitialized,
+    ];
+
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            MuteError::AlreadyInitialized => "Already initialized",
+            MuteError::MoreGasRequired => "More gas is required",
+            MuteError::InsufficientBalance => "The account doesn't have enough balance",
+            MuteError::TradingNotEnabled => "Trading is not enabled yet",
+            MuteError::SenderInCooldown => "The account is in cooldown until",
+            MuteError::MaxTxExceeded => "The transfer amount exceeds the max transaction amount",
+            MuteError::MaxWalletExceeded => "The receiver balance exceeds the max wallet balance",
+            MuteError::SelfTransferBlocked => "Tokens can't be sent to the token contract",
+            MuteError::ReceiverBlocked => "Transfers are blocked for the receiver",
+            MuteError::ContractReceiverRequiresCall => {
+                "Use ft_transfer_call to send tokens to the contract"
+            }
+            MuteError::RegistrationDepositTooSmall => {
+                "The attached deposit must cover the receiver's storage of"
+            }
+            MuteError::AccountNotRegistered => "The account is not registered",
+            MuteError::OneYoctoRequired => "Requires attached deposit of exactly 1 yoctoNEAR",
+            MuteError::NotOwner => "Only the owner can call this method",
+            MuteError::FeeReceiverCantUnregister => "Fee receivers can't unregister",
+            MuteError::AccountHasLockedTokens => "The account has locked tokens",
+            MuteError::AccountHasStake => "The account has staked tokens or unclaimed rewards",
+            MuteError::AccountHasAuctionCommitment => {
+                "The account has an unclaimed auction commitment"
+            }
+            MuteError::PositiveBalanceWithoutForce => {
+                "Can't unregister the account with the positive balance without force"
+            }
+            MuteError::NotInitialized => "The contract is not initialized",
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|error| error.code() == code)
+    }
+
+    /// Finds the error in a panic message as reported by a node or a client library, e.g.
+    /// `Smart contract panicked: E003: The account doesn't have enough balance`.
+    pub fn from_panic_message(message: &str) -> Option<Self> {
+        message.match_indices('E').find_map(|(index, _)| {
+            let code = message.get(index + 1..index + 4)?;
+            if !code.bytes().all(|b| b.is_ascii_digit())
+                || message.get(index + 4..index + 5) != Some(":")
+            {
+                return None;
+            }
+            Self::from_code(code.parse().ok()?)
+        })
+    }
+
+    pub fn view(self) -> ErrorCodeView {
+        ErrorCodeView {
+            code: self.code(),
+            error: self,
+            message: self.message(),
+        }
+    }
+
+    /// Panics with the error unless `condition` holds.
+    pub(crate) fn check(self, condition: bool) {
+        require!(condition, self.to_string());
+    }
+
+    /// Panics with the error followed by `detail` unless `condition` holds.
+    pub(crate) fn check_with(self, condition: bool, detail: impl fmt::Display) {
+        require!(condition, format!("{} {}", self, detail));
+    }
+
+    /// Panics with the error.
+    pub(crate) fn fail(self) -> ! {
+        self.check(false);
+        unreachable!()
+    }
+}
+
+/// `near_sdk::assert_one_yocto` with the error code.
+pub(crate) fn assert_one_yocto() {
+    MuteError::OneYoctoRequired.check(env::attached_deposit() == 1);
+}
+
+impl fmt::Display for MuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{:03}: {}", self.code(), self.message())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_are_unique_and_round_trip() {
+        for (index, error) in MuteError::ALL.into_iter().enumerate() {
+            assert_eq!(error.code(), index as u32 + 1);
+            assert_eq!(MuteError::from_code(error.code()), Some(error));
+            let panic = format!("Smart contract panicked: {} @bob.near", error);
+            assert_eq!(MuteError::from_panic_message(&panic), Some(error));
+        }
+        assert_eq!(MuteError::from_code(0), None);
+    }
+
+    #[test]
+    fn test_unknown_panic_message() {
+        assert_eq!(
+            MuteError::from_panic_message("Smart contract panicked: Error: E12 not a code"),
+            None
+        );
+        assert_eq!(MuteError::from_panic_message("E999: Unknown"), None);
+        assert_eq!(
+            MuteError::from_panic_message("Smart contract panicked: E006: The transfer amount exceeds the max transaction amount"),
+            Some(MuteError::MaxTxExceeded)
+        );
+        assert_eq!(
+            MuteError::from_panic_message(
+                "Smart contract panicked: E012: The account is not registered @bob.near"
+            ),
+            Some(MuteError::AccountNotRegistered)
+        );
+        assert_eq!(
+            MuteError::from_panic_message(
+                "Smart contract panicked: E013: Requires attached deposit of exactly 1 yoctoNEAR"
+            ),
+            Some(MuteError::OneYoctoRequired)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Contract-specific events in the NEP-297 format, next to the standard NEP-141 ones.
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::Serialize;
+use near_sdk::{env, AccountId};
+
+pub const EVENT_STANDARD: &str = "mute";
+pub const EVENT_VERSION: &str = "1.0.0";
+
+#[must_use]
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+pub enum MuteEvent<'a> {
+    TransferLimitsUpdated {
+        max_tx_amount: Option<U128>,
+        max_wallet_balance: Option<U128>,
+    },
+    LimitExemptionUpdated {
+        account_id: &'a AccountId,
+        limit: crate::limits::LimitKind,
+        exempt: bool,
+    },
+    TradingEnabled {
+        timestamp: U64,
+        cooldown_secs: u64,
+        cooldown_hours: u64,
+    },
+    TradingAllowlistUpdated {
+        account_id: &'a AccountId,
+        allowed: bool,
+    },
+    FeeCharged {
+        sender_id: &'a AccountId,
+        fee: U128,
+        burned: U128,
+        reflected: U128,
+        staking: U128,
+        accrued: U128,
+    },
+    FeesClaimed {
+        account_id: &'a AccountId,
+        amount: U128,
+        shares: &'a [FeeSplitShare<'a>],
+    },
+    FeeScheduleUpdated {
+        schedule: &'a crate::fees::FeeSchedule,
+    },
+    AccountClassUpdated {
+        account_id: &'a AccountId,
+        class: Option<crate::fees::AccountClass>,
+    },
+    FeeDistributionUpdated {
+        shares: &'a [crate::fees::FeeShare],
+    },
+    FeeDiscountTiersUpdated {
+        tiers: &'a [crate::fees::FeeDiscountTier],
+    },
+    NonCirculatingAccountUpdated {
+        account_id: &'a AccountId,
+        non_circulating: bool,
+    },
+    TokensLocked {
+        account_id: &'a AccountId,
+        amount: U128,
+        unlock_at: U64,
+    },
+    TokensUnlocked {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    Staked {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    Unstaked {
+        account_id: &'a AccountId,
+        amount: U128,
+        unbonding_until: U64,
+    },
+    UnstakedWithdrawn {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    StakingRewardsClaimed {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    StakingRewardsFunded {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    StakingRewardsSkipped {
+        amount: U128,
+        total_staked: U128,
+    },
+    StakingConfigUpdated {
+        reward_rate: U128,
+        fee_bps: u16,
+        unbonding_period_secs: u64,
+    },
+    FeeBurnRateUpdated {
+        burn_bps: u16,
+    },
+    ReflectionRateUpdated {
+        reflection_bps: u16,
+    },
+    ReflectionExclusionUpdated {
+        account_id: &'a AccountId,
+        excluded: bool,
+    },
+    WrapModeUpdated {
+        enabled: bool,
+    },
+    SaleConfigUpdated {
+        config: &'a crate::sale::SaleConfig,
+    },
+    SalePhaseUpdated {
+        phase: crate::sale::SalePhase,
+    },
+    SaleAllowlistUpdated {
+        account_id: &'a AccountId,
+        allowed: bool,
+    },
+    SaleProceedsWithdrawn {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    TokensBought {
+        account_id: &'a AccountId,
+        amount: U128,
+        cost: U128,
+    },
+    TokensSold {
+        account_id: &'a AccountId,
+        amount: U128,
+        payout: U128,
+    },
+    AuctionStarted {
+        config: &'a crate::auction::AuctionConfig,
+    },
+    AuctionCommitted {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    AuctionFinalized {
+        clearing_price: U128,
+        sold: U128,
+    },
+    AuctionClaimed {
+        account_id: &'a AccountId,
+        amount: U128,
+        refund: U128,
+    },
+    AuctionProceedsWithdrawn {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    TokensRescued {
+        token_contract: &'a AccountId,
+        receiver_id: &'a AccountId,
+        amount: U128,
+    },
+    NearRescued {
+        receiver_id: &'a AccountId,
+        amount: U128,
+    },
+    ReceiverPolicyUpdated {
+        policy: crate::receivers::ReceiverPolicy,
+    },
+    BlockedReceiverUpdated {
+        account_id: &'a AccountId,
+        blocked: bool,
+    },
+    SponsorConfigUpdated {
+        config: &'a crate::sponsor::SponsorConfig,
+    },
+    SponsorPoolFunded {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    SponsorPoolWithdrawn {
+        account_id: &'a AccountId,
+        amount: U128,
+    },
+    RegistrationSponsored {
+        account_id: &'a AccountId,
+        sponsor_id: &'a AccountId,
+        amount: U128,
+    },
+    SponsoredDepositReturned {
+        account_id: &'a AccountId,
+        sponsor_id: &'a AccountId,
+        amount: U128,
+    },
+    ClosedBalancePolicyUpdated {
+        policy: crate::storage::ClosedBalancePolicy,
+    },
+    ClosedBalanceDisposed {
+        account_id: &'a AccountId,
+        amount: U128,
+        receiver_id: Option<&'a AccountId>,
+    },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeSplitShare<'a> {
+    pub account_id: &'a AccountId,
+    pub amount: U128,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct EventLog<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event: &'a MuteEvent<'a>,
+}
+
+impl MuteEvent<'_> {
+    pub fn emit(self) {
+        let log = EventLog {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event: &self,
+        };
+        let json = near_sdk::serde_json::to_string(&log).unwrap_or_else(|_| env::abort());
+        env::log_str(&format!("EVENT_JSON:{}", json));
+    }
+}
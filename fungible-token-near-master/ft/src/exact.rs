@@ -0,0 +1,174 @@
+//! Sender-pays transfers: the fee is added on top of the amount, so the receiver gets exactly
+//! the requested amount.
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferQuote {
+    /// Debited from the sender.
+    pub amount: U128,
+    pub fee: U128,
+    /// Credited to the receiver.
+    pub received: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Quotes a transfer between the two accounts. With `exact`, `amount` is what the receiver
+    /// should get and the fee is added on top, as in `ft_transfer_exact`.
+    pub fn ft_quote_transfer(
+        &self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        exact: Option<bool>,
+    ) -> TransferQuote {
+        let amount = if exact.unwrap_or(false) {
+            self.gross_up(&sender_id, &receiver_id, amount.0)
+        } else {
+            amount.0
+        };
+        let (received, fee) = self.calculate_fee(&sender_id, &receiver_id, U128(amount));
+        TransferQuote {
+            amount: U128(amount),
+            fee: fee.unwrap_or(U128(0)),
+            received,
+        }
+    }
+
+    /// Like `ft_transfer`, but the receiver gets exactly `net_amount` and the fee is debited
+    /// from the sender on top of it.
+    #[payable]
+    pub fn ft_transfer_exact(
+        &mut self,
+        receiver_id: AccountId,
+        net_amount: U128,
+        memo: Option<String>,
+    ) {
+        let sender_id = env::predecessor_account_id();
+        self.assert_deposit_and_register_receiver(&sender_id, &receiver_id);
+        let amount = self.gross_up(&sender_id, &receiver_id, net_amount.0);
+        self.internal_ft_transfer(&sender_id, &receiver_id, U128(amount), memo);
+    }
+
+    /// Like `ft_transfer_call`, but `ft_on_transfer` is called with exactly `net_amount` and the
+    /// fee is charged on top of it when the transfer is resolved.
+    #[payable]
+    pub fn ft_transfer_call_exact(
+        &mut self,
+        receiver_id: AccountId,
+        net_amount: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let sender_id = env::predecessor_account_id();
+        self.assert_deposit_and_register_receiver(&sender_id, &receiver_id);
+        let amount = self.gross_up(&sender_id, &receiver_id, net_amount.0);
+        self.internal_ft_transfer_call(sender_id, receiver_id, U128(amount), memo, msg)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user, resolve_with};
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract
+    }
+
+    #[test]
+    fn test_transfer_exact() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let quote = contract.ft_quote_transfer(accounts(1), accounts(2), U128(90), Some(true));
+        assert_eq!(
+            quote,
+            TransferQuote {
+                amount: U128(99),
+                fee: U128(9),
+                received: U128(90),
+            }
+        );
+
+        contract.ft_transfer_exact(accounts(2), U128(90), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 90);
+        assert_eq!(
+            contract.ft_balance_of(accounts(1)).0,
+            DEFAULT_TOTAL_SUPPLY - 99
+        );
+        assert_eq!(contract.ft_accrued_fees().0, 9);
+    }
+
+    #[test]
+    fn test_quote_matches_plain_transfer() {
+        let ctx = &mut get_context(accounts(1));
+        let contract = setup(ctx);
+        assert_eq!(
+            contract.ft_quote_transfer(accounts(1), accounts(2), U128(1_005), None),
+            TransferQuote {
+                amount: U128(1_005),
+                fee: U128(100),
+                received: U128(905),
+            }
+        );
+        // Transfers to the fee receiver are untaxed.
+        assert_eq!(
+            contract
+                .ft_quote_transfer(accounts(1), accounts(3), U128(1_005), Some(true))
+                .amount,
+            U128(1_005)
+        );
+    }
+
+    #[test]
+    fn test_gross_up_is_minimal() {
+        let ctx = &mut get_context(accounts(1));
+        let contract = setup(ctx);
+        for net in (1..100u128).chain([899, 900, 901, 123_457]) {
+            let gross = contract.gross_up(&accounts(1), &accounts(2), net);
+            let (received, _) = contract.calculate_fee(&accounts(1), &accounts(2), U128(gross));
+            let (below, _) = contract.calculate_fee(&accounts(1), &accounts(2), U128(gross - 1));
+            assert_eq!(received.0, net);
+            assert!(below.0 < net);
+        }
+    }
+
+    #[test]
+    fn test_transfer_call_exact() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.ft_transfer_call_exact(accounts(2), U128(900), None, "".into());
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+
+        resolve_with(ctx, PromiseResult::Successful(b"\"0\"".to_vec()));
+        assert_eq!(
+            contract.ft_resolve_transfer_with_fee(
+                accounts(1),
+                accounts(2),
+                U128(900),
+                U128(99),
+                None
+            ),
+            U128(900)
+        );
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+        assert_eq!(
+            contract.ft_balance_of(accounts(1)).0,
+            DEFAULT_TOTAL_SUPPLY - 999
+        );
+        assert_eq!(contract.ft_accrued_fees().0, 99);
+    }
+}
@@ -0,0 +1,361 @@
+//! Opt-in per-account ring buffer of recent transfers.
+//!
+//! An account reserves a fixed number of slots by attaching a storage deposit to
+//! `ft_history_enable`. The deposit is reported as part of its NEP-145 storage balance and is
+//! refunded by `ft_history_disable` or when the account unregisters.
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde::Serialize;
+use near_sdk::{IntoStorageKey, Promise};
+
+pub const MAX_HISTORY_CAPACITY: u32 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    In,
+    Out,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HistoryEntry {
+    pub counterparty: AccountId,
+    pub amount: U128,
+    pub fee: U128,
+    pub direction: TransferDirection,
+    pub memo_hash: Option<Base58CryptoHash>,
+    pub block_height: U64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct HistoryMeta {
+    pub capacity: u32,
+    /// Number of entries ever written, the next slot is `count % capacity`.
+    pub count: u64,
+    pub deposit: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TransferHistory {
+    meta: LookupMap<AccountId, HistoryMeta>,
+    entries: LookupMap<(AccountId, u32), HistoryEntry>,
+    meta_storage_usage: StorageUsage,
+    entry_storage_usage: StorageUsage,
+    /// Sum of the history deposits of all accounts, owed back to them on disable.
+    pub total_deposit: Balance,
+}
+
+impl TransferHistory {
+    pub fn new<S, T>(meta_prefix: S, entries_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        let mut this = Self {
+            meta: LookupMap::new(meta_prefix),
+            entries: LookupMap::new(entries_prefix),
+            meta_storage_usage: 0,
+            entry_storage_usage: 0,
+            total_deposit: 0,
+        };
+        this.measure_storage_usage();
+        this
+    }
+
+    fn measure_storage_usage(&mut self) {
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        let initial_storage_usage = env::storage_usage();
+        let meta = HistoryMeta {
+            capacity: 0,
+            count: 0,
+            deposit: 0,
+        };
+        self.meta.insert(&tmp_account_id, &meta);
+        self.meta_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.meta.remove(&tmp_account_id);
+
+        let initial_storage_usage = env::storage_usage();
+        let entry = HistoryEntry {
+            counterparty: tmp_account_id.clone(),
+            amount: U128(0),
+            fee: U128(0),
+            direction: TransferDirection::In,
+            memo_hash: Some(Base58CryptoHash::default()),
+            block_height: U64(0),
+        };
+        self.entries.insert(&(tmp_account_id.clone(), 0), &entry);
+        self.entry_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.entries.remove(&(tmp_account_id, 0));
+    }
+
+    pub fn storage_cost(&self, capacity: u32) -> Balance {
+        let storage_usage =
+            self.meta_storage_usage + self.entry_storage_usage * StorageUsage::from(capacity);
+        Balance::from(storage_usage) * env::storage_byte_cost()
+    }
+
+    pub fn deposit_of(&self, account_id: &AccountId) -> Balance {
+        self.meta.get(account_id).map(|m| m.deposit).unwrap_or(0)
+    }
+
+    pub fn record(&mut self, account_id: &AccountId, entry: &HistoryEntry) {
+        if let Some(mut meta) = self.meta.get(account_id) {
+            let slot = (meta.count % u64::from(meta.capacity)) as u32;
+            self.entries.insert(&(account_id.clone(), slot), entry);
+            meta.count += 1;
+            self.meta.insert(account_id, &meta);
+        }
+    }
+
+    /// Removes the account's history and returns the storage deposit that backed it.
+    pub fn clear(&mut self, account_id: &AccountId) -> Balance {
+        match self.meta.remove(account_id) {
+            Some(meta) => {
+                let used = std::cmp::min(meta.count, u64::from(meta.capacity)) as u32;
+                for slot in 0..used {
+                    self.entries.remove(&(account_id.clone(), slot));
+                }
+                self.total_deposit -= meta.deposit;
+                meta.deposit
+            }
+            None => 0,
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Reserves `capacity` history slots for the predecessor. The attached deposit must cover
+    /// `ft_history_storage_cost(capacity)`, the excess is refunded.
+    #[payable]
+    pub fn ft_history_enable(&mut self, capacity: u32) {
+        let account_id = env::predecessor_account_id();
+        self.assert_registered(&account_id);
+        require!(
+            capacity > 0 && capacity <= MAX_HISTORY_CAPACITY,
+            format!("Capacity must be between 1 and {}", MAX_HISTORY_CAPACITY)
+        );
+        require!(
+            self.history.meta.get(&account_id).is_none(),
+            "History is already enabled"
+        );
+        let cost = self.history.storage_cost(capacity);
+        let amount = env::attached_deposit();
+        require!(
+            amount >= cost,
+            format!("Requires attached deposit of at least {}", cost)
+        );
+        self.history.meta.insert(
+            &account_id,
+            &HistoryMeta {
+                capacity,
+                count: 0,
+                deposit: cost,
+            },
+        );
+        self.history.total_deposit += cost;
+        if amount > cost {
+            Promise::new(account_id).transfer(amount - cost);
+        }
+    }
+
+    /// Drops the predecessor's history and refunds its storage deposit.
+    #[payable]
+    pub fn ft_history_disable(&mut self) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let deposit = self.history.clear(&account_id);
+        require!(deposit > 0, "History is not enabled");
+        Promise::new(account_id).transfer(deposit + 1);
+    }
+
+    pub fn ft_history_storage_cost(&self, capacity: u32) -> U128 {
+        self.history.storage_cost(capacity).into()
+    }
+
+    /// Returns the account's recent transfers, newest first.
+    pub fn ft_history(
+        &self,
+        account_id: AccountId,
+        from_index: Option<u64>,
+        limit: Option<u64>,
+    ) -> Vec<HistoryEntry> {
+        let meta = match self.history.meta.get(&account_id) {
+            Some(meta) => meta,
+            None => return vec![],
+        };
+        let capacity = u64::from(meta.capacity);
+        let len = std::cmp::min(meta.count, capacity);
+        let start = from_index.unwrap_or(0);
+        let end = std::cmp::min(len, start.saturating_add(limit.unwrap_or(capacity)));
+        (start..end)
+            .filter_map(|i| {
+                let slot = ((meta.count - 1 - i) % capacity) as u32;
+                self.history.entries.get(&(account_id.clone(), slot))
+            })
+            .collect()
+    }
+}
+
+impl Contract {
+    pub(crate) fn record_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        fee: Balance,
+        memo: Option<&str>,
+    ) {
+        let memo_hash = memo.map(|memo| Base58CryptoHash::from(env::sha256_array(memo.as_bytes())));
+        let block_height = U64(env::block_height());
+        let mut entry = HistoryEntry {
+            counterparty: receiver_id.clone(),
+            amount: U128(amount),
+            fee: U128(fee),
+            direction: TransferDirection::Out,
+            memo_hash,
+            block_height,
+        };
+        self.history.record(sender_id, &entry);
+        entry.counterparty = sender_id.clone();
+        entry.direction = TransferDirection::In;
+        self.history.record(receiver_id, &entry);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::{testing_env, PromiseResult};
+
+    use super::*;
+    use crate::tests::{get_context, register_user, resolve_with};
+
+    fn enable_history(
+        ctx: &mut near_sdk::test_utils::VMContextBuilder,
+        contract: &mut Contract,
+        id: usize,
+        capacity: u32,
+    ) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(contract.ft_history_storage_cost(capacity).0)
+            .predecessor_account_id(accounts(id))
+            .build());
+        contract.ft_history_enable(capacity);
+    }
+
+    fn transfer(
+        ctx: &mut near_sdk::test_utils::VMContextBuilder,
+        contract: &mut Contract,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .block_index(amount as u64)
+            .build());
+        contract.ft_transfer(accounts(2), amount.into(), memo);
+    }
+
+    #[test]
+    fn test_history_ring_buffer() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 2);
+        enable_history(ctx, contract, 2, 2);
+
+        transfer(ctx, contract, 100, Some("first".into()));
+        transfer(ctx, contract, 200, None);
+        transfer(ctx, contract, 300, None);
+
+        let history = contract.ft_history(accounts(2), None, None);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].amount, U128(270));
+        assert_eq!(history[0].fee, U128(30));
+        assert_eq!(history[0].counterparty, accounts(1));
+        assert_eq!(history[0].direction, TransferDirection::In);
+        assert_eq!(history[0].block_height, U64(300));
+        assert_eq!(history[1].amount, U128(180));
+        assert_eq!(
+            contract.ft_history(accounts(2), Some(1), Some(5)),
+            history[1..].to_vec()
+        );
+        // The sender never opted in.
+        assert!(contract.ft_history(accounts(1), None, None).is_empty());
+    }
+
+    #[test]
+    fn test_history_memo_hash() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 2);
+        enable_history(ctx, contract, 2, 5);
+        transfer(ctx, contract, 100, Some("invoice 42".into()));
+
+        let history = contract.ft_history(accounts(2), None, None);
+        assert_eq!(
+            history[0].memo_hash,
+            Some(env::sha256_array(b"invoice 42").into())
+        );
+    }
+
+    #[test]
+    fn test_history_memo_hash_of_transfer_call() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 2);
+        enable_history(ctx, contract, 2, 5);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let memo = Some("invoice 43".to_string());
+        contract.ft_transfer_call(accounts(2), U128(1_000), memo.clone(), "".into());
+
+        resolve_with(ctx, PromiseResult::Successful(b"\"0\"".to_vec()));
+        contract.ft_resolve_transfer_with_fee(accounts(1), accounts(2), U128(900), U128(100), memo);
+        let history = contract.ft_history(accounts(2), None, None);
+        assert_eq!(history[0].amount, U128(900));
+        assert_eq!(
+            history[0].memo_hash,
+            Some(env::sha256_array(b"invoice 43").into())
+        );
+    }
+
+    #[test]
+    fn test_history_deposit_in_storage_balance() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 2);
+        let min = contract.storage_balance_bounds().min.0;
+        let cost = contract.ft_history_storage_cost(10).0;
+        enable_history(ctx, contract, 2, 10);
+        assert_eq!(
+            contract.storage_balance_of(accounts(2)).unwrap().total.0,
+            min + cost
+        );
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.ft_history_disable();
+        assert_eq!(
+            contract.storage_balance_of(accounts(2)).unwrap().total.0,
+            min
+        );
+        assert!(contract.ft_history(accounts(2), None, None).is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+//! Enumeration of registered token holders.
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    /// Returns registered accounts with their balances, in registration order.
+    pub fn ft_holders(
+        &self,
+        from_index: Option<U128>,
+        limit: Option<u64>,
+    ) -> Vec<(AccountId, U128)> {
+        let start = u128::from(from_index.unwrap_or(U128(0)));
+        require!(
+            (self.holders.len() as u128) >= start,
+            "Out of bounds, please use a smaller from_index."
+        );
+        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
+        require!(limit != 0, "Cannot provide limit of 0.");
+        self.holders
+            .iter()
+            .skip(start as usize)
+            .take(limit)
+            .map(|account_id| {
+                let balance = self.ft_balance_of(account_id.clone());
+                (account_id, balance)
+            })
+            .collect()
+    }
+
+    pub fn ft_holders_count(&self) -> U64 {
+        self.holders.len().into()
+    }
+}
+
+impl Contract {
+    /// Storage taken by one entry of the holder index, charged on top of the balance record.
+    pub(crate) fn measure_holder_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.holders.insert(&tmp_account_id);
+        let holder_storage_usage = env::storage_usage() - initial_storage_usage;
+        self.holders.remove(&tmp_account_id);
+        holder_storage_usage
+    }
+
+    pub(crate) fn internal_register_account(&mut self, account_id: &AccountId) {
+        self.token.internal_register_account(account_id);
+        self.holders.insert(account_id);
+    }
+
+    /// The stored balance of a registered account, without pending reflections.
+    pub(crate) fn internal_registered_balance(&self, account_id: &AccountId) -> Balance {
+        let balance = self.token.accounts.get(account_id);
+        MuteError::AccountNotRegistered
+            .check_with(balance.is_some(), format_args!("@{}", account_id));
+        balance.unwrap_or_default()
+    }
+
+    pub(crate) fn assert_registered(&self, account_id: &AccountId) {
+        MuteError::AccountNotRegistered.check_with(
+            self.token.accounts.contains_key(account_id),
+            format_args!("@{}", account_id),
+        );
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    #[test]
+    fn test_holders_follow_registration() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        assert_eq!(contract.ft_holders_count().0, 2);
+
+        register_user(ctx, contract, 2);
+        assert_eq!(contract.ft_holders_count().0, 3);
+        assert_eq!(
+            contract.ft_holders(Some(U128(1)), Some(2)),
+            vec![(accounts(3), U128(0)), (accounts(2), U128(0))]
+        );
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert!(contract.storage_unregister(None));
+        assert_eq!(contract.ft_holders_count().0, 2);
+        assert_eq!(
+            contract.ft_holders(None, None),
+            vec![
+                (accounts(1), U128(DEFAULT_TOTAL_SUPPLY)),
+                (accounts(3), U128(0))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_storage_bounds_include_holder_index() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        let plain = FungibleToken::new(b"t".to_vec()).account_storage_usage;
+        let holder = contract.measure_holder_storage_usage();
+        let last_send = contract.launch.measure_account_storage_usage();
+        let snapshot = contract.reflection.measure_account_storage_usage();
+        let lock = contract.locks.measure_account_storage_usage();
+        let stake = contract.staking.measure_account_storage_usage();
+        let purchase = contract.sale.measure_account_storage_usage();
+        let commitment = contract.auction.measure_account_storage_usage();
+        let sponsor_usage = contract.sponsor.measure_account_storage_usage();
+        let wrapped = contract.measure_wrap_storage_usage();
+        assert!(holder > 0);
+        assert_eq!(
+            contract.storage_balance_bounds().min.0,
+            Balance::from(
+                plain
+                    + holder
+                    + last_send
+                    + snapshot
+                    + lock
+                    + stake
+                    + purchase
+                    + commitment
+                    + sponsor_usage
+                    + wrapped
+            ) * env::storage_byte_cost()
+        );
+    }
+}
@@ -0,0 +1,243 @@
+//! Launch mode: transfers are gated until the owner enables trading, after which an optional
+//! per-account cooldown between sends applies for a limited period.
+use crate::errors::MuteError;
+use crate::events::MuteEvent;
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{Duration, IntoStorageKey, Timestamp};
+
+pub const NANOS_PER_SECOND: u64 = 1_000_000_000;
+const SECONDS_PER_HOUR: u64 = 3_600;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct LaunchControl {
+    pub trading_enabled_at: Option<Timestamp>,
+    /// Accounts that can send before trading is enabled and are never subject to the cooldown.
+    pub allowlist: UnorderedSet<AccountId>,
+    pub cooldown: Duration,
+    /// How long after trading is enabled the cooldown applies.
+    pub cooldown_period: Duration,
+    pub last_send: LookupMap<AccountId, Timestamp>,
+}
+
+impl LaunchControl {
+    pub fn new<S, T>(allowlist_prefix: S, last_send_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            trading_enabled_at: None,
+            allowlist: UnorderedSet::new(allowlist_prefix),
+            cooldown: 0,
+            cooldown_period: 0,
+            last_send: LookupMap::new(last_send_prefix),
+        }
+    }
+
+    /// Storage taken by one `last_send` record, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.last_send.insert(&tmp_account_id, &0);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.last_send.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn cooldown_active(&self, now: Timestamp) -> bool {
+        match self.trading_enabled_at {
+            Some(enabled_at) => self.cooldown > 0 && now < enabled_at + self.cooldown_period,
+            None => false,
+        }
+    }
+
+    /// Returns when `account_id` can send next, or `None` while trading is disabled for it.
+    pub fn next_send_at(&self, account_id: &AccountId, now: Timestamp) -> Option<Timestamp> {
+        if self.allowlist.contains(account_id) {
+            return Some(now);
+        }
+        self.trading_enabled_at?;
+        if !self.cooldown_active(now) {
+            return Some(now);
+        }
+        let next = self
+            .last_send
+            .get(account_id)
+            .map_or(now, |last| last + self.cooldown);
+        Some(std::cmp::max(now, next))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LaunchConfigView {
+    pub trading_enabled_at: Option<U64>,
+    pub cooldown_secs: u64,
+    pub cooldown_hours: u64,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn trading_enabled(&self) -> bool {
+        self.launch.trading_enabled_at.is_some()
+    }
+
+    pub fn launch_config(&self) -> LaunchConfigView {
+        LaunchConfigView {
+            trading_enabled_at: self.launch.trading_enabled_at.map(U64),
+            cooldown_secs: self.launch.cooldown / NANOS_PER_SECOND,
+            cooldown_hours: self.launch.cooldown_period / NANOS_PER_SECOND / SECONDS_PER_HOUR,
+        }
+    }
+
+    /// Block timestamp in nanoseconds from which `account_id` can send again, or `None` while
+    /// trading is disabled for it.
+    pub fn ft_next_send_at(&self, account_id: AccountId) -> Option<U64> {
+        self.launch
+            .next_send_at(&account_id, env::block_timestamp())
+            .map(U64)
+    }
+
+    pub fn ft_can_send_now(&self, account_id: AccountId) -> bool {
+        let now = env::block_timestamp();
+        self.launch.next_send_at(&account_id, now) == Some(now)
+    }
+
+    pub fn trading_allowlist(&self) -> Vec<AccountId> {
+        self.launch.allowlist.to_vec()
+    }
+
+    #[payable]
+    pub fn set_trading_allowlisted(&mut self, account_id: AccountId, allowed: bool) {
+        self.assert_owner();
+        let changed = if allowed {
+            self.launch.allowlist.insert(&account_id)
+        } else {
+            self.launch.allowlist.remove(&account_id)
+        };
+        if changed {
+            MuteEvent::TradingAllowlistUpdated {
+                account_id: &account_id,
+                allowed,
+            }
+            .emit();
+        }
+    }
+
+    /// Opens transfers to everyone. For the first `cooldown_hours` each account not on the
+    /// allowlist has to wait `cooldown_secs` between consecutive sends.
+    #[payable]
+    pub fn enable_trading(&mut self, cooldown_secs: Option<u64>, cooldown_hours: Option<u64>) {
+        self.assert_owner();
+        require!(
+            self.launch.trading_enabled_at.is_none(),
+            "Trading is already enabled"
+        );
+        let now = env::block_timestamp();
+        self.launch.trading_enabled_at = Some(now);
+        self.launch.cooldown = cooldown_secs.unwrap_or(0) * NANOS_PER_SECOND;
+        self.launch.cooldown_period =
+            cooldown_hours.unwrap_or(0) * SECONDS_PER_HOUR * NANOS_PER_SECOND;
+        MuteEvent::TradingEnabled {
+            timestamp: U64(now),
+            cooldown_secs: cooldown_secs.unwrap_or(0),
+            cooldown_hours: cooldown_hours.unwrap_or(0),
+        }
+        .emit();
+    }
+}
+
+impl Contract {
+    /// Enforces the launch gate and the cooldown for an outgoing transfer of `sender_id`.
+    pub(crate) fn assert_can_send(&mut self, sender_id: &AccountId) {
+        let now = env::block_timestamp();
+        match self.launch.next_send_at(sender_id, now) {
+            None => MuteError::TradingNotEnabled.check(false),
+            Some(next) => MuteError::SenderInCooldown.check_with(next <= now, next),
+        }
+        if self.launch.cooldown_active(now) && !self.launch.allowlist.contains(sender_id) {
+            self.launch.last_send.insert(sender_id, &now);
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    const SECOND: u64 = NANOS_PER_SECOND;
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        register_user(ctx, &mut contract, 4);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        // The owner is allowlisted and can seed wallets before launch.
+        contract.ft_transfer(accounts(2), U128(10_000), None);
+        contract
+    }
+
+    fn send_at(ctx: &mut VMContextBuilder, contract: &mut Contract, timestamp: u64) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(timestamp)
+            .build());
+        contract.ft_transfer(accounts(4), U128(100), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Trading is not enabled yet")]
+    fn test_transfers_gated_before_launch() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        assert_eq!(contract.ft_next_send_at(accounts(2)), None);
+        send_at(ctx, &mut contract, 0);
+    }
+
+    #[test]
+    fn test_cooldown_after_launch() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        testing_env!(ctx.block_timestamp(1_000 * SECOND).build());
+        contract.enable_trading(Some(60), Some(1));
+        assert!(contract.trading_enabled());
+
+        send_at(ctx, &mut contract, 1_000 * SECOND);
+        assert_eq!(
+            contract.ft_next_send_at(accounts(2)),
+            Some(U64(1_060 * SECOND))
+        );
+        assert!(!contract.ft_can_send_now(accounts(2)));
+
+        send_at(ctx, &mut contract, 1_060 * SECOND);
+        // Once the cooldown period is over, sends are unrestricted.
+        send_at(ctx, &mut contract, 4_600 * SECOND);
+        send_at(ctx, &mut contract, 4_601 * SECOND);
+        assert!(contract.ft_can_send_now(accounts(2)));
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 4 * 90);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is in cooldown until 1060000000000")]
+    fn test_cooldown_rejects_early_send() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        testing_env!(ctx.block_timestamp(1_000 * SECOND).build());
+        contract.enable_trading(Some(60), Some(1));
+        send_at(ctx, &mut contract, 1_000 * SECOND);
+        send_at(ctx, &mut contract, 1_059 * SECOND);
+    }
+}
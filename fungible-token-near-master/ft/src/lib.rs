@@ -15,30 +15,97 @@ NOTES:
   - To prevent the deployed contract from being modified or deleted, it should not have any access
     keys on its account.
 */
+use auction::DutchAuction;
+use errors::{assert_one_yocto, ErrorCodeView, MuteError};
+use fees::{AccountClass, FeeDiscountTier, FeeSchedule, FeeShare};
+use history::TransferHistory;
+use launch::LaunchControl;
+use limits::TransferLimits;
+use locks::TokenLocks;
 use near_contract_standards::fungible_token::core::FungibleTokenCore;
 use near_contract_standards::fungible_token::metadata::{
     FungibleTokenMetadata, FungibleTokenMetadataProvider, FT_METADATA_SPEC,
 };
 use near_contract_standards::fungible_token::receiver::ext_ft_receiver;
-use near_contract_standards::fungible_token::resolver::{ext_ft_resolver, FungibleTokenResolver};
+use near_contract_standards::fungible_token::resolver::FungibleTokenResolver;
 use near_contract_standards::fungible_token::FungibleToken;
+use near_contract_standards::storage_management::{
+    StorageBalance, StorageBalanceBounds, StorageManagement,
+};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LazyOption;
-use near_sdk::json_types::U128;
+use near_sdk::collections::{LazyOption, LookupMap, UnorderedSet};
+use near_sdk::json_types::{U128, U64};
 use near_sdk::{
-    assert_one_yocto, env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas,
-    PanicOnDefault, PromiseOrValue, PromiseResult,
+    env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas, PromiseOrValue,
+    PromiseResult, StorageUsage,
 };
+use receivers::ReceiverGuard;
+use reflection::Reflection;
+use sale::TokenSale;
+use sponsor::SponsorPool;
+use staking::StakingVault;
+use stats::FeeStats;
+use storage::ClosedBalancePolicy;
+
+mod auction;
+pub mod errors;
+mod events;
+mod exact;
+mod fees;
+mod history;
+mod holders;
+mod launch;
+mod limits;
+mod locks;
+mod math;
+mod owner;
+mod receivers;
+mod reflection;
+mod rescue;
+mod sale;
+mod sponsor;
+mod staking;
+mod stats;
+mod storage;
+mod supply;
+mod views;
+mod wrap;
 
 const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
 const GAS_FOR_FT_TRANSFER_CALL: Gas = Gas(25_000_000_000_000 + GAS_FOR_RESOLVE_TRANSFER.0);
 
 #[near_bindgen]
-#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+#[derive(BorshDeserialize, BorshSerialize)]
 pub struct Contract {
     token: FungibleToken,
     metadata: LazyOption<FungibleTokenMetadata>,
     fee_receiver: AccountId,
+    holders: UnorderedSet<AccountId>,
+    owner_id: AccountId,
+    non_circulating: UnorderedSet<AccountId>,
+    history: TransferHistory,
+    limits: TransferLimits,
+    launch: LaunchControl,
+    fee_shares: Vec<FeeShare>,
+    fee_burn_bps: u16,
+    total_burned: Balance,
+    reflection: Reflection,
+    accrued_fees: Balance,
+    claimed_fees: Balance,
+    fee_schedule: FeeSchedule,
+    account_classes: LookupMap<AccountId, AccountClass>,
+    locks: TokenLocks,
+    fee_discount_tiers: Vec<FeeDiscountTier>,
+    fee_stats: FeeStats,
+    staking: StakingVault,
+    wrap_enabled: bool,
+    wrapped_near: Balance,
+    wrapped_balances: LookupMap<AccountId, Balance>,
+    sale: TokenSale,
+    auction: DutchAuction,
+    receivers: ReceiverGuard,
+    sponsor: SponsorPool,
+    closed_balance_policy: ClosedBalancePolicy,
 }
 
 const DATA_IMAGE_SVG_NEAR_ICON: &str = "data:image/svg+xml,%3Csvg xmlns='http://www.w3.org/2000/svg' viewBox='0 0 288 288'%3E%3Cg id='l' data-name='l'%3E%3Cpath d='M187.58,79.81l-30.1,44.69a3.2,3.2,0,0,0,4.75,4.2L191.86,103a1.2,1.2,0,0,1,2,.91v80.46a1.2,1.2,0,0,1-2.12.77L102.18,77.93A15.35,15.35,0,0,0,90.47,72.5H87.34A15.34,15.34,0,0,0,72,87.84V201.16A15.34,15.34,0,0,0,87.34,216.5h0a15.35,15.35,0,0,0,13.08-7.31l30.1-44.69a3.2,3.2,0,0,0-4.75-4.2L96.14,186a1.2,1.2,0,0,1-2-.91V104.61a1.2,1.2,0,0,1,2.12-.77l89.55,107.23a15.35,15.35,0,0,0,11.71,5.43h3.13A15.34,15.34,0,0,0,216,201.16V87.84A15.34,15.34,0,0,0,200.66,72.5h0A15.35,15.35,0,0,0,187.58,79.81Z'/%3E%3C/g%3E%3C/svg%3E";
@@ -50,6 +117,36 @@ pub const TRANSFER_FEE_BPS: u128 = 10_00;
 enum StorageKey {
     FungibleToken,
     Metadata,
+    Holders,
+    NonCirculating,
+    HistoryMeta,
+    HistoryEntries,
+    MaxTxExempt,
+    MaxWalletExempt,
+    TradingAllowlist,
+    LastSend,
+    ReflectionExcluded,
+    ReflectionSnapshots,
+    AccountClasses,
+    Locks,
+    FeesPaid,
+    FeeEpochs,
+    StakeAccounts,
+    SalePurchases,
+    SaleAllowlist,
+    AuctionCommitments,
+    BlockedReceivers,
+    SponsorUsage,
+    WrappedBalances,
+    SponsoredAccounts,
+}
+
+/// The state is read with `Default` as the fallback, so calls before `new` fail with
+/// `NotInitialized`.
+impl Default for Contract {
+    fn default() -> Self {
+        MuteError::NotInitialized.fail()
+    }
 }
 
 #[near_bindgen]
@@ -79,61 +176,84 @@ impl Contract {
         total_supply: U128,
         metadata: FungibleTokenMetadata,
     ) -> Self {
-        require!(!env::state_exists(), "Already initialized");
+        MuteError::AlreadyInitialized.check(!env::state_exists());
         metadata.assert_valid();
         let mut this = Self {
             token: FungibleToken::new(StorageKey::FungibleToken),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
             fee_receiver: fee_receiver.clone(),
+            holders: UnorderedSet::new(StorageKey::Holders),
+            owner_id: owner_id.clone(),
+            non_circulating: UnorderedSet::new(StorageKey::NonCirculating),
+            history: TransferHistory::new(StorageKey::HistoryMeta, StorageKey::HistoryEntries),
+            limits: TransferLimits::new(StorageKey::MaxTxExempt, StorageKey::MaxWalletExempt),
+            launch: LaunchControl::new(StorageKey::TradingAllowlist, StorageKey::LastSend),
+            fee_shares: Vec::new(),
+            fee_burn_bps: 0,
+            total_burned: 0,
+            reflection: Reflection::new(
+                StorageKey::ReflectionExcluded,
+                StorageKey::ReflectionSnapshots,
+            ),
+            accrued_fees: 0,
+            claimed_fees: 0,
+            fee_schedule: FeeSchedule::default(),
+            account_classes: LookupMap::new(StorageKey::AccountClasses),
+            locks: TokenLocks::new(StorageKey::Locks),
+            fee_discount_tiers: Vec::new(),
+            fee_stats: FeeStats::new(StorageKey::FeesPaid, StorageKey::FeeEpochs),
+            staking: StakingVault::new(StorageKey::StakeAccounts),
+            wrap_enabled: false,
+            wrapped_near: 0,
+            wrapped_balances: LookupMap::new(StorageKey::WrappedBalances),
+            sale: TokenSale::new(StorageKey::SalePurchases, StorageKey::SaleAllowlist),
+            auction: DutchAuction::new(StorageKey::AuctionCommitments),
+            receivers: ReceiverGuard::new(StorageKey::BlockedReceivers),
+            sponsor: SponsorPool::new(StorageKey::SponsorUsage, StorageKey::SponsoredAccounts),
+            closed_balance_policy: ClosedBalancePolicy::Burn,
         };
-        this.token.internal_register_account(&owner_id);
+        this.token.account_storage_usage += this.measure_holder_storage_usage();
+        this.token.account_storage_usage += this.launch.measure_account_storage_usage();
+        this.token.account_storage_usage += this.reflection.measure_account_storage_usage();
+        this.token.account_storage_usage += this.locks.measure_account_storage_usage();
+        this.token.account_storage_usage += this.staking.measure_account_storage_usage();
+        this.token.account_storage_usage += this.sale.measure_account_storage_usage();
+        this.token.account_storage_usage += this.auction.measure_account_storage_usage();
+        this.token.account_storage_usage += this.sponsor.measure_account_storage_usage();
+        this.token.account_storage_usage += this.measure_wrap_storage_usage();
+        this.reflection.excluded.insert(&env::current_account_id());
+        this.launch.allowlist.insert(&owner_id);
+        this.launch.allowlist.insert(&fee_receiver);
+        this.internal_register_account(&owner_id);
         if owner_id.ne(&fee_receiver) {
-            this.token.internal_register_account(&fee_receiver);
+            this.internal_register_account(&fee_receiver);
         }
         this.token.internal_deposit(&owner_id, total_supply.into());
         this
     }
 
-    fn on_account_closed(&mut self, account_id: AccountId, balance: Balance) {
-        log!("Closed @{} with {}", account_id, balance);
+    fn on_account_closed(&mut self, account_id: AccountId) {
+        self.holders.remove(&account_id);
+        self.launch.last_send.remove(&account_id);
+        self.reflection.snapshots.remove(&account_id);
+        self.sale.purchases.remove(&account_id);
+        self.sponsor.usage.remove(&account_id);
+        self.release_wrapped_balance(&account_id);
     }
 
     fn on_tokens_burned(&mut self, account_id: AccountId, amount: Balance) {
+        self.total_burned += amount;
         log!("Account @{} burned {}", account_id, amount);
     }
-
-    fn calculate_fee(
-        &self,
-        sender_id: &AccountId,
-        receiver_id: &AccountId,
-        amount: U128,
-    ) -> (U128, Option<U128>) {
-        let amount = amount.0;
-
-        if sender_id.ne(&self.fee_receiver) && receiver_id.ne(&self.fee_receiver) {
-            let fee = amount * TRANSFER_FEE_BPS / 10_000;
-
-            (U128(amount - fee), Some(fee.into()))
-        } else {
-            (amount.into(), None)
-        }
-    }
 }
 
-near_contract_standards::impl_fungible_token_storage!(Contract, token, on_account_closed);
-
 #[near_bindgen]
 impl FungibleTokenCore for Contract {
     #[payable]
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>) {
-        let (amount, fee) =
-            self.calculate_fee(&env::predecessor_account_id(), &receiver_id, amount);
-
-        if let Some(fee) = fee {
-            self.token
-                .ft_transfer(self.fee_receiver.clone(), fee, Some("transfer fees".into()));
-        }
-        self.token.ft_transfer(receiver_id, amount, memo);
+        let sender_id = env::predecessor_account_id();
+        self.assert_deposit_and_register_receiver(&sender_id, &receiver_id);
+        self.internal_ft_transfer(&sender_id, &receiver_id, amount, memo);
     }
 
     #[payable]
@@ -144,36 +264,150 @@ impl FungibleTokenCore for Contract {
         memo: Option<String>,
         msg: String,
     ) -> PromiseOrValue<U128> {
-        assert_one_yocto();
-        require!(
-            env::prepaid_gas() > GAS_FOR_FT_TRANSFER_CALL,
-            "More gas is required"
-        );
         let sender_id = env::predecessor_account_id();
+        self.assert_deposit_and_register_receiver(&sender_id, &receiver_id);
+        self.internal_ft_transfer_call(sender_id, receiver_id, amount, memo, msg)
+    }
 
-        let total = amount;
-        let (amount, _) = self.calculate_fee(&sender_id, &receiver_id, total);
+    fn ft_total_supply(&self) -> U128 {
+        self.token.ft_total_supply()
+    }
 
-        self.token
-            .internal_transfer(&sender_id, &receiver_id, amount.into(), memo);
+    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        self.internal_balance_of(&account_id).into()
+    }
+}
+
+impl Contract {
+    /// Transfers `amount` minus the fee to the receiver, `amount` is debited from the sender.
+    pub(crate) fn internal_ft_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: U128,
+        memo: Option<String>,
+    ) {
+        self.assert_can_send(sender_id);
+        self.assert_receiver_allowed(receiver_id, false);
+        self.assert_registered(sender_id);
+        self.assert_registered(receiver_id);
+        MuteError::InsufficientBalance.check(self.internal_balance_of(sender_id) >= amount.0);
+        let (amount, fee) = self.calculate_fee(sender_id, receiver_id, amount);
+
+        self.internal_transfer(sender_id, receiver_id, amount.into(), memo.clone());
+        self.assert_transfer_limits(Some(sender_id), receiver_id, amount.into());
+        // The fee is charged once the amount has left the sender, so no part of the reflected
+        // fee goes back to the sender for tokens it gives away.
+        if let Some(fee) = fee {
+            self.distribute_fee(sender_id, fee.into());
+        }
+        self.record_transfer(
+            sender_id,
+            receiver_id,
+            amount.into(),
+            fee.map_or(0, |fee| fee.0),
+            memo.as_deref(),
+        );
+    }
+
+    /// Sends `total` minus the fee to the receiver's `ft_on_transfer`. The fee is charged in
+    /// `ft_resolve_transfer`, so the sender must hold the whole `total` upfront.
+    pub(crate) fn internal_ft_transfer_call(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        total: U128,
+        memo: Option<String>,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        MuteError::MoreGasRequired.check(env::prepaid_gas() > GAS_FOR_FT_TRANSFER_CALL);
+        self.assert_can_send(&sender_id);
+        self.assert_receiver_allowed(&receiver_id, true);
+        self.assert_registered(&sender_id);
+        self.assert_registered(&receiver_id);
+        MuteError::InsufficientBalance.check(self.internal_balance_of(&sender_id) >= total.0);
+        let (amount, fee) = self.calculate_fee(&sender_id, &receiver_id, total);
+
+        self.internal_transfer(&sender_id, &receiver_id, amount.into(), memo.clone());
+        self.assert_transfer_limits(Some(&sender_id), &receiver_id, amount.into());
 
         ext_ft_receiver::ext(receiver_id.clone())
             .with_static_gas(env::prepaid_gas() - GAS_FOR_FT_TRANSFER_CALL)
-            .ft_on_transfer(sender_id.clone(), amount.into(), msg)
+            .ft_on_transfer(sender_id.clone(), amount, msg)
             .then(
-                ext_ft_resolver::ext(env::current_account_id())
+                Self::ext(env::current_account_id())
                     .with_static_gas(GAS_FOR_RESOLVE_TRANSFER)
-                    .ft_resolve_transfer(sender_id, receiver_id, total.into()),
+                    .ft_resolve_transfer_with_fee(
+                        sender_id,
+                        receiver_id,
+                        amount,
+                        fee.unwrap_or(U128(0)),
+                        memo,
+                    ),
             )
             .into()
     }
 
-    fn ft_total_supply(&self) -> U128 {
-        self.token.ft_total_supply()
+    /// Settles a `ft_transfer_call` of `amount` to the receiver. `fee` is charged from the
+    /// sender if the receiver's call succeeded. `memo` goes to the transfer history.
+    fn internal_resolve_transfer(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: Balance,
+        fee: Balance,
+        memo: Option<String>,
+    ) -> U128 {
+        let mut charged_fee = 0;
+        if fee > 0 {
+            if let PromiseResult::Successful(_) = env::promise_result(0) {
+                self.distribute_fee(&sender_id, fee);
+                charged_fee = fee;
+            }
+        }
+        self.settle_reflection(&sender_id);
+        self.settle_reflection(&receiver_id);
+        let (used_amount, burned_amount) =
+            self.token
+                .internal_ft_resolve_transfer(&sender_id, receiver_id.clone(), amount.into());
+        if used_amount > 0 {
+            self.record_transfer(
+                &sender_id,
+                &receiver_id,
+                used_amount,
+                charged_fee,
+                memo.as_deref(),
+            );
+        }
+        if burned_amount > 0 {
+            self.on_tokens_burned(sender_id, burned_amount);
+        }
+
+        used_amount.into()
     }
+}
 
-    fn ft_balance_of(&self, account_id: AccountId) -> U128 {
-        self.token.ft_balance_of(account_id)
+#[near_bindgen]
+impl Contract {
+    /// Codes and messages of the errors the token methods panic with.
+    pub fn ft_error_codes(&self) -> Vec<ErrorCodeView> {
+        MuteError::ALL.into_iter().map(MuteError::view).collect()
+    }
+
+    /// Callback of `ft_transfer_call`. Unlike `ft_resolve_transfer` it takes the amount sent to
+    /// the receiver and the fee as they were computed at call time, so changes to the fee
+    /// schedule or account classes in between don't affect the settlement. The memo of the
+    /// transfer is passed along for the transfer history.
+    #[private]
+    pub fn ft_resolve_transfer_with_fee(
+        &mut self,
+        sender_id: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+        fee: U128,
+        memo: Option<String>,
+    ) -> U128 {
+        self.internal_resolve_transfer(sender_id, receiver_id, amount.0, fee.0, memo)
     }
 }
 
@@ -187,28 +421,13 @@ impl FungibleTokenResolver for Contract {
         amount: U128,
     ) -> U128 {
         let (amount, fee) = self.calculate_fee(&sender_id, &receiver_id, amount);
-
-        if let Some(fee) = fee {
-            match env::promise_result(0) {
-                PromiseResult::Successful(_) => {
-                    self.token.internal_transfer(
-                        &sender_id,
-                        &self.fee_receiver,
-                        fee.into(),
-                        Some("transfer_call fees".into()),
-                    );
-                }
-                _ => (),
-            }
-        }
-        let (used_amount, burned_amount) =
-            self.token
-                .internal_ft_resolve_transfer(&sender_id, receiver_id, amount);
-        if burned_amount > 0 {
-            self.on_tokens_burned(sender_id, burned_amount);
-        }
-
-        used_amount.into()
+        self.internal_resolve_transfer(
+            sender_id,
+            receiver_id,
+            amount.0,
+            fee.map_or(0, |fee| fee.0),
+            None,
+        )
     }
 }
 
@@ -220,15 +439,15 @@ impl FungibleTokenMetadataProvider for Contract {
 }
 
 #[cfg(all(test, not(target_arch = "wasm32")))]
-mod tests {
+pub(crate) mod tests {
     use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, Balance};
+    use near_sdk::{testing_env, Balance, RuntimeFeesConfig, VMConfig};
 
     use super::*;
 
     const TOTAL_SUPPLY: Balance = DEFAULT_TOTAL_SUPPLY;
 
-    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+    pub(crate) fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
         let mut builder = VMContextBuilder::new();
         builder
             .current_account_id(accounts(0))
@@ -237,7 +456,7 @@ mod tests {
         builder
     }
 
-    fn register_user(ctx: &mut VMContextBuilder, contract: &mut Contract, id: usize) {
+    pub(crate) fn register_user(ctx: &mut VMContextBuilder, contract: &mut Contract, id: usize) {
         testing_env!(ctx
             .storage_usage(env::storage_usage())
             .attached_deposit(contract.storage_balance_bounds().min.into())
@@ -247,18 +466,33 @@ mod tests {
         contract.storage_deposit(None, None);
     }
 
+    /// Sets up the environment of a callback from the contract itself with the given result of
+    /// the previous promise.
+    pub(crate) fn resolve_with(ctx: &mut VMContextBuilder, result: PromiseResult) {
+        testing_env!(
+            ctx.storage_usage(env::storage_usage())
+                .attached_deposit(0)
+                .predecessor_account_id(accounts(0))
+                .build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+    }
+
     #[test]
     fn test_new() {
         let mut ctx = get_context(accounts(1));
         testing_env!(ctx.build());
-        let contract = Contract::new_default_config(accounts(1).into(), accounts(2));
+        let contract = Contract::new_default_config(accounts(1), accounts(2));
         testing_env!(ctx.is_view(true).build());
         assert_eq!(contract.ft_total_supply().0, TOTAL_SUPPLY);
         assert_eq!(contract.ft_balance_of(accounts(1)).0, TOTAL_SUPPLY);
     }
 
     #[test]
-    #[should_panic(expected = "The contract is not initialized")]
+    #[should_panic(expected = "E020: The contract is not initialized")]
     fn test_default() {
         let ctx = get_context(accounts(1));
         testing_env!(ctx.build());
@@ -269,7 +503,7 @@ mod tests {
     fn test_transfer() {
         let ctx = &mut get_context(accounts(1));
         testing_env!(ctx.build());
-        let contract = &mut Contract::new_default_config(accounts(1).into(), accounts(3));
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
         register_user(ctx, contract, 2);
 
         testing_env!(ctx
@@ -295,6 +529,65 @@ mod tests {
             contract.ft_balance_of(accounts(2)).0,
             transfer_amount - expected_fee
         );
-        assert_eq!(contract.ft_balance_of(accounts(3)).0, expected_fee);
+        assert_eq!(contract.ft_accrued_fees().0, expected_fee);
+    }
+
+    #[test]
+    fn test_ft_error_codes() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = Contract::new_default_config(accounts(1), accounts(3));
+        let codes = contract.ft_error_codes();
+        assert_eq!(codes.len(), MuteError::ALL.len());
+        assert_eq!(
+            codes.last(),
+            Some(&ErrorCodeView {
+                code: 20,
+                error: MuteError::NotInitialized,
+                message: "The contract is not initialized",
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E003: The account doesn't have enough balance")]
+    fn test_transfer_insufficient_balance() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 2);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.enable_trading(None, None);
+        testing_env!(ctx.predecessor_account_id(accounts(2)).build());
+        contract.ft_transfer(accounts(1), U128(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E012: The account is not registered @charlie")]
+    fn test_transfer_from_unregistered_account() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.enable_trading(None, None);
+        testing_env!(ctx.predecessor_account_id(accounts(2)).build());
+        contract.ft_transfer(accounts(1), U128(1), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E013: Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_transfer_requires_one_yocto() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx.predecessor_account_id(accounts(1)).build());
+        contract.ft_transfer(accounts(3), U128(1), None);
     }
 }
@@ -0,0 +1,254 @@
+//! Anti-whale limits on the size of a single transfer and on the balance of a single wallet.
+use crate::errors::MuteError;
+use crate::events::MuteEvent;
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::IntoStorageKey;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum LimitKind {
+    MaxTx,
+    MaxWallet,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TransferLimits {
+    pub max_tx_amount: Option<Balance>,
+    pub max_wallet_balance: Option<Balance>,
+    /// Accounts that may send or receive any amount in a single transfer, e.g. the treasury.
+    pub max_tx_exempt: UnorderedSet<AccountId>,
+    /// Accounts that may hold any balance, e.g. liquidity pools and the treasury.
+    pub max_wallet_exempt: UnorderedSet<AccountId>,
+}
+
+impl TransferLimits {
+    pub fn new<S, T>(max_tx_exempt_prefix: S, max_wallet_exempt_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            max_tx_amount: None,
+            max_wallet_balance: None,
+            max_tx_exempt: UnorderedSet::new(max_tx_exempt_prefix),
+            max_wallet_exempt: UnorderedSet::new(max_wallet_exempt_prefix),
+        }
+    }
+
+    fn exempt(&self, limit: LimitKind) -> &UnorderedSet<AccountId> {
+        match limit {
+            LimitKind::MaxTx => &self.max_tx_exempt,
+            LimitKind::MaxWallet => &self.max_wallet_exempt,
+        }
+    }
+
+    fn exempt_mut(&mut self, limit: LimitKind) -> &mut UnorderedSet<AccountId> {
+        match limit {
+            LimitKind::MaxTx => &mut self.max_tx_exempt,
+            LimitKind::MaxWallet => &mut self.max_wallet_exempt,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TransferLimitsView {
+    pub max_tx_amount: Option<U128>,
+    pub max_wallet_balance: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn transfer_limits(&self) -> TransferLimitsView {
+        TransferLimitsView {
+            max_tx_amount: self.limits.max_tx_amount.map(U128),
+            max_wallet_balance: self.limits.max_wallet_balance.map(U128),
+        }
+    }
+
+    pub fn limit_exempt_accounts(&self, limit: LimitKind) -> Vec<AccountId> {
+        self.limits.exempt(limit).to_vec()
+    }
+
+    /// `None` removes the limit.
+    #[payable]
+    pub fn set_max_tx_amount(&mut self, max_tx_amount: Option<U128>) {
+        self.assert_owner();
+        require!(
+            max_tx_amount != Some(U128(0)),
+            "The limit should be a positive number"
+        );
+        self.limits.max_tx_amount = max_tx_amount.map(|v| v.0);
+        self.emit_limits_updated();
+    }
+
+    /// `None` removes the limit.
+    #[payable]
+    pub fn set_max_wallet_balance(&mut self, max_wallet_balance: Option<U128>) {
+        self.assert_owner();
+        require!(
+            max_wallet_balance != Some(U128(0)),
+            "The limit should be a positive number"
+        );
+        self.limits.max_wallet_balance = max_wallet_balance.map(|v| v.0);
+        self.emit_limits_updated();
+    }
+
+    #[payable]
+    pub fn set_limit_exempt(&mut self, account_id: AccountId, limit: LimitKind, exempt: bool) {
+        self.assert_owner();
+        let changed = if exempt {
+            self.limits.exempt_mut(limit).insert(&account_id)
+        } else {
+            self.limits.exempt_mut(limit).remove(&account_id)
+        };
+        if changed {
+            MuteEvent::LimitExemptionUpdated {
+                account_id: &account_id,
+                limit,
+                exempt,
+            }
+            .emit();
+        }
+    }
+}
+
+impl Contract {
+    fn emit_limits_updated(&self) {
+        let view = self.transfer_limits();
+        MuteEvent::TransferLimitsUpdated {
+            max_tx_amount: view.max_tx_amount,
+            max_wallet_balance: view.max_wallet_balance,
+        }
+        .emit();
+    }
+
+    /// Checks `amount` sent by `sender_id` against the max transaction amount, for sends that
+    /// credit no receiver.
+    pub(crate) fn assert_max_tx(&self, sender_id: &AccountId, amount: Balance) {
+        if let Some(max_tx_amount) = self.limits.max_tx_amount {
+            if !self.limits.max_tx_exempt.contains(sender_id) {
+                MuteError::MaxTxExceeded.check(amount <= max_tx_amount);
+            }
+        }
+    }
+
+    /// Checks the net `amount` credited to `receiver_id` against the limits. Must be called after
+    /// the receiver's balance was updated. `sender_id` is `None` for mints.
+    pub(crate) fn assert_transfer_limits(
+        &self,
+        sender_id: Option<&AccountId>,
+        receiver_id: &AccountId,
+        amount: Balance,
+    ) {
+        if let Some(max_tx_amount) = self.limits.max_tx_amount {
+            let exempt = &self.limits.max_tx_exempt;
+            let sender_exempt = matches!(sender_id, Some(id) if exempt.contains(id));
+            if !sender_exempt && !exempt.contains(receiver_id) {
+                MuteError::MaxTxExceeded.check(amount <= max_tx_amount);
+            }
+        }
+        if let Some(max_wallet_balance) = self.limits.max_wallet_balance {
+            if !self.limits.max_wallet_exempt.contains(receiver_id) {
+                MuteError::MaxWalletExceeded
+                    .check(self.internal_balance_of(receiver_id) <= max_wallet_balance);
+            }
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract
+    }
+
+    #[test]
+    fn test_max_tx_boundary() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        // 10% fee: sending 1000 credits 900.
+        contract.set_max_tx_amount(Some(U128(900)));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+    }
+
+    #[test]
+    #[should_panic(expected = "The transfer amount exceeds the max transaction amount")]
+    fn test_max_tx_exceeded() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_max_tx_amount(Some(U128(899)));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+
+    #[test]
+    fn test_max_wallet_boundary() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_max_wallet_balance(Some(U128(1_800)));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 1_800);
+    }
+
+    #[test]
+    #[should_panic(expected = "The receiver balance exceeds the max wallet balance")]
+    fn test_max_wallet_exceeded() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_max_wallet_balance(Some(U128(1_799)));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+
+    #[test]
+    fn test_limit_exemptions() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_max_tx_amount(Some(U128(1)));
+        contract.set_max_wallet_balance(Some(U128(1)));
+        contract.set_limit_exempt(accounts(2), LimitKind::MaxTx, true);
+        contract.set_limit_exempt(accounts(2), LimitKind::MaxWallet, true);
+        assert!(get_logs().last().unwrap().contains(
+            r#""event":"limit_exemption_updated","data":{"account_id":"charlie","limit":"max_wallet","exempt":true}"#
+        ));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+        assert_eq!(
+            contract.limit_exempt_accounts(LimitKind::MaxTx),
+            vec![accounts(2)]
+        );
+    }
+
+    #[test]
+    fn test_limits_event() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_max_tx_amount(Some(U128(5)));
+        assert_eq!(
+            get_logs(),
+            vec![
+                r#"EVENT_JSON:{"standard":"mute","version":"1.0.0","event":"transfer_limits_updated","data":{"max_tx_amount":"5","max_wallet_balance":null}}"#
+            ]
+        );
+    }
+}
@@ -0,0 +1,199 @@
+//! Time locks: holders can lock MUTE until a given time to qualify for fee discounts.
+//!
+//! Locked tokens are taken out of the account balance, so they can't be transferred and don't
+//! take part in reflections, but they still count towards the total supply.
+use crate::events::MuteEvent;
+use crate::launch::NANOS_PER_SECOND;
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{IntoStorageKey, Timestamp};
+
+/// Longest lock, four years.
+pub const MAX_LOCK_DURATION_SECS: u64 = 4 * 365 * 24 * 3_600;
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct TokenLock {
+    pub amount: Balance,
+    pub unlock_at: Timestamp,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokenLocks {
+    pub locks: LookupMap<AccountId, TokenLock>,
+    pub total_locked: Balance,
+}
+
+impl TokenLocks {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            locks: LookupMap::new(prefix),
+            total_locked: 0,
+        }
+    }
+
+    /// Storage taken by one lock record, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        let lock = TokenLock {
+            amount: 0,
+            unlock_at: 0,
+        };
+        self.locks.insert(&tmp_account_id, &lock);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.locks.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    /// Amount locked by the account that is not unlockable yet.
+    pub fn active_amount(&self, account_id: &AccountId) -> Balance {
+        match self.locks.get(account_id) {
+            Some(lock) if lock.unlock_at > env::block_timestamp() => lock.amount,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenLockView {
+    pub amount: U128,
+    pub unlock_at: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn locked_balance_of(&self, account_id: AccountId) -> Option<TokenLockView> {
+        self.locks.locks.get(&account_id).map(|lock| TokenLockView {
+            amount: lock.amount.into(),
+            unlock_at: lock.unlock_at.into(),
+        })
+    }
+
+    pub fn ft_total_locked(&self) -> U128 {
+        self.locks.total_locked.into()
+    }
+
+    /// Locks `amount` of the predecessor's tokens for `duration_secs`. Adding to an existing lock
+    /// keeps the later of the two unlock times.
+    #[payable]
+    pub fn lock(&mut self, amount: U128, duration_secs: u64) -> TokenLockView {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        require!(
+            duration_secs > 0 && duration_secs <= MAX_LOCK_DURATION_SECS,
+            format!(
+                "Lock duration must be between 1 and {} seconds",
+                MAX_LOCK_DURATION_SECS
+            )
+        );
+        let account_id = env::predecessor_account_id();
+        self.internal_debit(&account_id, amount.0);
+
+        let unlock_at = env::block_timestamp() + duration_secs * NANOS_PER_SECOND;
+        let mut lock = self.locks.locks.get(&account_id).unwrap_or(TokenLock {
+            amount: 0,
+            unlock_at: 0,
+        });
+        lock.amount += amount.0;
+        lock.unlock_at = std::cmp::max(lock.unlock_at, unlock_at);
+        self.locks.locks.insert(&account_id, &lock);
+        self.locks.total_locked += amount.0;
+        MuteEvent::TokensLocked {
+            account_id: &account_id,
+            amount,
+            unlock_at: lock.unlock_at.into(),
+        }
+        .emit();
+        TokenLockView {
+            amount: lock.amount.into(),
+            unlock_at: lock.unlock_at.into(),
+        }
+    }
+
+    /// Returns the predecessor's locked tokens to its balance once the lock expired.
+    #[payable]
+    pub fn unlock(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let lock = self
+            .locks
+            .locks
+            .get(&account_id)
+            .unwrap_or_else(|| env::panic_str("The account has no locked tokens"));
+        require!(
+            lock.unlock_at <= env::block_timestamp(),
+            format!("The tokens are locked until {}", lock.unlock_at)
+        );
+        self.locks.locks.remove(&account_id);
+        self.locks.total_locked -= lock.amount;
+        self.internal_credit(&account_id, lock.amount);
+        MuteEvent::TokensUnlocked {
+            account_id: &account_id,
+            amount: lock.amount.into(),
+        }
+        .emit();
+        lock.amount.into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::get_context;
+
+    const DAY: u64 = 24 * 3_600;
+
+    fn at(ctx: &mut VMContextBuilder, secs: u64) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(secs * NANOS_PER_SECOND)
+            .build());
+    }
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        at(ctx, 0);
+        contract.lock(U128(1_000), 30 * DAY);
+        at(ctx, DAY);
+        // Extending with a shorter lock keeps the original unlock time.
+        let lock = contract.lock(U128(500), DAY);
+        assert_eq!(lock.amount, U128(1_500));
+        assert_eq!(lock.unlock_at, U64(30 * DAY * NANOS_PER_SECOND));
+        assert_eq!(
+            contract.ft_balance_of(accounts(1)).0,
+            DEFAULT_TOTAL_SUPPLY - 1_500
+        );
+        assert_eq!(contract.ft_total_supply().0, DEFAULT_TOTAL_SUPPLY);
+        assert_eq!(contract.ft_total_locked().0, 1_500);
+        assert_eq!(contract.locks.active_amount(&accounts(1)), 1_500);
+
+        at(ctx, 30 * DAY);
+        assert_eq!(contract.locks.active_amount(&accounts(1)), 0);
+        assert_eq!(contract.unlock(), U128(1_500));
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, DEFAULT_TOTAL_SUPPLY);
+        assert_eq!(contract.locked_balance_of(accounts(1)), None);
+        assert_eq!(contract.ft_total_locked().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The tokens are locked until")]
+    fn test_unlock_before_expiry() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        at(ctx, 0);
+        contract.lock(U128(1_000), DAY);
+        at(ctx, DAY - 1);
+        contract.unlock();
+    }
+}
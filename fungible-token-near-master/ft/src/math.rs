@@ -0,0 +1,27 @@
+//! Wide arithmetic for share and price calculations.
+#![allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+
+uint::construct_uint! {
+    pub struct U256(4);
+}
+
+/// `a * b / c` rounded down, without intermediate overflow. Panics if the result doesn't fit.
+pub fn mul_div(a: u128, b: u128, c: u128) -> u128 {
+    (U256::from(a) * U256::from(b) / U256::from(c)).as_u128()
+}
+
+/// `a * b / c` rounded up.
+pub fn mul_div_ceil(a: u128, b: u128, c: u128) -> u128 {
+    let c = U256::from(c);
+    ((U256::from(a) * U256::from(b) + c - 1) / c).as_u128()
+}
+
+/// `a * b / c` rounded down, `None` if the result doesn't fit in `u128`.
+pub fn checked_mul_div(a: u128, b: u128, c: u128) -> Option<u128> {
+    let result = U256::from(a) * U256::from(b) / U256::from(c);
+    if result > U256::from(u128::MAX) {
+        None
+    } else {
+        Some(result.as_u128())
+    }
+}
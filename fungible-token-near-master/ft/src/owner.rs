@@ -0,0 +1,16 @@
+//! Ownership of the administrative methods.
+use crate::*;
+
+#[near_bindgen]
+impl Contract {
+    pub fn get_owner(&self) -> AccountId {
+        self.owner_id.clone()
+    }
+}
+
+impl Contract {
+    pub(crate) fn assert_owner(&self) {
+        assert_one_yocto();
+        MuteError::NotOwner.check(env::predecessor_account_id() == self.owner_id);
+    }
+}
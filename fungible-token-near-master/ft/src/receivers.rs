@@ -0,0 +1,174 @@
+//! Checks on the receiver of a transfer, so tokens aren't lost by sending them to the token
+//! contract itself, to configured sink accounts, or to a contract that only accounts for tokens
+//! arriving through `ft_transfer_call`.
+//!
+//! Contract receivers are the accounts in the `contract` account class. All checks are off
+//! until the owner enables them.
+use crate::errors::MuteError;
+use crate::events::MuteEvent;
+use crate::fees::AccountClass;
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::IntoStorageKey;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReceiverPolicy {
+    /// Rejects transfers to the token contract account.
+    pub block_self: bool,
+    /// Rejects plain `ft_transfer` to accounts in the `contract` class.
+    pub require_call_for_contracts: bool,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct ReceiverGuard {
+    pub block_self: bool,
+    pub require_call_for_contracts: bool,
+    /// Accounts that may not receive tokens at all.
+    pub sinks: UnorderedSet<AccountId>,
+}
+
+impl ReceiverGuard {
+    pub fn new<S>(sinks_prefix: S) -> Self
+    where
+        S: IntoStorageKey,
+    {
+        Self {
+            block_self: false,
+            require_call_for_contracts: false,
+            sinks: UnorderedSet::new(sinks_prefix),
+        }
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn receiver_policy(&self) -> ReceiverPolicy {
+        ReceiverPolicy {
+            block_self: self.receivers.block_self,
+            require_call_for_contracts: self.receivers.require_call_for_contracts,
+        }
+    }
+
+    pub fn blocked_receivers(&self) -> Vec<AccountId> {
+        self.receivers.sinks.to_vec()
+    }
+
+    #[payable]
+    pub fn set_receiver_policy(&mut self, policy: ReceiverPolicy) {
+        self.assert_owner();
+        self.receivers.block_self = policy.block_self;
+        self.receivers.require_call_for_contracts = policy.require_call_for_contracts;
+        MuteEvent::ReceiverPolicyUpdated { policy }.emit();
+    }
+
+    /// Blocks or unblocks transfers to `account_id`, e.g. the fee receiver.
+    #[payable]
+    pub fn set_blocked_receiver(&mut self, account_id: AccountId, blocked: bool) {
+        self.assert_owner();
+        let changed = if blocked {
+            self.receivers.sinks.insert(&account_id)
+        } else {
+            self.receivers.sinks.remove(&account_id)
+        };
+        if changed {
+            MuteEvent::BlockedReceiverUpdated {
+                account_id: &account_id,
+                blocked,
+            }
+            .emit();
+        }
+    }
+}
+
+impl Contract {
+    /// Panics if `receiver_id` may not receive a transfer. `via_call` tells whether the
+    /// transfer is a `ft_transfer_call`.
+    pub(crate) fn assert_receiver_allowed(&self, receiver_id: &AccountId, via_call: bool) {
+        if self.receivers.block_self {
+            MuteError::SelfTransferBlocked.check(receiver_id != &env::current_account_id());
+        }
+        MuteError::ReceiverBlocked.check_with(
+            !self.receivers.sinks.contains(receiver_id),
+            format_args!("@{}", receiver_id),
+        );
+        if self.receivers.require_call_for_contracts && !via_call {
+            MuteError::ContractReceiverRequiresCall.check_with(
+                self.account_classes.get(receiver_id) != Some(AccountClass::Contract),
+                format_args!("@{}", receiver_id),
+            );
+        }
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        for id in [0, 2] {
+            register_user(ctx, &mut contract, id);
+        }
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.set_receiver_policy(ReceiverPolicy {
+            block_self: true,
+            require_call_for_contracts: true,
+        });
+        contract
+    }
+
+    #[test]
+    #[should_panic(expected = "Tokens can't be sent to the token contract")]
+    fn test_self_transfer_blocked() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.ft_transfer(accounts(0), U128(1_000), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E009: Transfers are blocked for the receiver @danny")]
+    fn test_sink_blocked() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_blocked_receiver(accounts(3), true);
+        contract.ft_transfer(accounts(3), U128(1_000), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E010: Use ft_transfer_call to send tokens to the contract @charlie")]
+    fn test_contract_receiver_requires_call() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_account_class(accounts(2), Some(AccountClass::Contract));
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+
+    #[test]
+    fn test_contract_receiver_accepts_call() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_account_class(accounts(2), Some(AccountClass::Contract));
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .prepaid_gas(Gas(300_000_000_000_000))
+            .build());
+        contract.ft_transfer_call(accounts(2), U128(1_000), None, String::new());
+        assert!(contract.ft_balance_of(accounts(2)).0 > 0);
+
+        contract.set_receiver_policy(ReceiverPolicy {
+            block_self: true,
+            require_call_for_contracts: false,
+        });
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+}
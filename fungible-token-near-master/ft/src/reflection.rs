@@ -0,0 +1,402 @@
+//! Reflection rewards: a share of each transfer fee is redistributed to all holders
+//! proportionally to their balances, without iterating over accounts.
+//!
+//! Reflected tokens are moved into a pool and a global `per_token` accumulator grows by
+//! `amount / eligible_supply`. An account's balance is its stored balance plus its pending share
+//! `stored * (per_token - snapshot)`, which is settled into the stored balance every time the
+//! account is touched. Excluded accounts neither receive nor dilute reflections.
+//!
+//! Pending shares and the accumulator are rounded down, so every reflection and every settlement
+//! can leave up to one unit of dust in the pool. The sum of all balances therefore never exceeds
+//! the total supply and trails it only by that dust.
+use crate::events::MuteEvent;
+use crate::math::{checked_mul_div, mul_div};
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::IntoStorageKey;
+
+pub const REFLECTION_PRECISION: u128 = 1_000_000_000_000_000_000_000_000_000_000;
+/// Keeps the eligible supply computation bounded.
+pub const MAX_REFLECTION_EXCLUDED: u64 = 20;
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Reflection {
+    /// Share of each transfer fee that is reflected, in bps of the fee.
+    pub bps: u16,
+    /// Accumulated reflections per unit of eligible balance, scaled by `REFLECTION_PRECISION`.
+    pub per_token: u128,
+    /// Reflected tokens that were not yet settled into account balances.
+    pub pool: Balance,
+    pub excluded: UnorderedSet<AccountId>,
+    pub snapshots: LookupMap<AccountId, u128>,
+}
+
+impl Reflection {
+    pub fn new<S, T>(excluded_prefix: S, snapshots_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            bps: 0,
+            per_token: 0,
+            pool: 0,
+            excluded: UnorderedSet::new(excluded_prefix),
+            snapshots: LookupMap::new(snapshots_prefix),
+        }
+    }
+
+    /// Storage taken by one snapshot record, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.snapshots.insert(&tmp_account_id, &0);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.snapshots.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn pending(&self, account_id: &AccountId, stored: Balance) -> Balance {
+        if stored == 0 || self.excluded.contains(account_id) {
+            return 0;
+        }
+        let snapshot = self.snapshots.get(account_id).unwrap_or(0);
+        mul_div(stored, self.per_token - snapshot, REFLECTION_PRECISION)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReflectionView {
+    pub bps: u16,
+    pub pool: U128,
+    pub excluded: Vec<AccountId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn reflection_info(&self) -> ReflectionView {
+        ReflectionView {
+            bps: self.reflection.bps,
+            pool: self.reflection.pool.into(),
+            excluded: self.reflection.excluded.to_vec(),
+        }
+    }
+
+    #[payable]
+    pub fn set_reflection_bps(&mut self, reflection_bps: u16) {
+        self.assert_owner();
+        require!(
+            u32::from(reflection_bps)
+                + u32::from(self.fee_burn_bps)
+                + u32::from(self.staking.fee_bps)
+                <= u32::from(fees::FEE_BPS_DENOMINATOR),
+            "Burn, reflection and staking shares can't exceed 10000 bps"
+        );
+        self.reflection.bps = reflection_bps;
+        MuteEvent::ReflectionRateUpdated { reflection_bps }.emit();
+    }
+
+    #[payable]
+    pub fn set_reflection_excluded(&mut self, account_id: AccountId, excluded: bool) {
+        self.assert_owner();
+        if excluded == self.reflection.excluded.contains(&account_id) {
+            return;
+        }
+        if excluded {
+            require!(
+                self.reflection.excluded.len() < MAX_REFLECTION_EXCLUDED,
+                "Too many accounts excluded from reflections"
+            );
+            self.settle_reflection(&account_id);
+            self.reflection.excluded.insert(&account_id);
+            self.reflection.snapshots.remove(&account_id);
+        } else {
+            self.reflection.excluded.remove(&account_id);
+            if self.token.accounts.contains_key(&account_id) {
+                self.reflection
+                    .snapshots
+                    .insert(&account_id, &self.reflection.per_token);
+            }
+        }
+        MuteEvent::ReflectionExclusionUpdated {
+            account_id: &account_id,
+            excluded,
+        }
+        .emit();
+    }
+}
+
+impl Contract {
+    /// Balance including the account's unsettled reflections.
+    pub(crate) fn internal_balance_of(&self, account_id: &AccountId) -> Balance {
+        let stored = self.token.accounts.get(account_id).unwrap_or(0);
+        stored + self.reflection.pending(account_id, stored)
+    }
+
+    /// Moves the account's pending reflections into its stored balance. Must be called before
+    /// the stored balance changes.
+    pub(crate) fn settle_reflection(&mut self, account_id: &AccountId) {
+        let stored = match self.token.accounts.get(account_id) {
+            Some(stored) => stored,
+            None => return,
+        };
+        if self.reflection.excluded.contains(account_id) {
+            return;
+        }
+        let pending = self.reflection.pending(account_id, stored);
+        if pending > 0 {
+            self.token.accounts.insert(account_id, &(stored + pending));
+            self.reflection.pool -= pending;
+        }
+        if self.reflection.snapshots.get(account_id) != Some(self.reflection.per_token) {
+            self.reflection
+                .snapshots
+                .insert(account_id, &self.reflection.per_token);
+        }
+    }
+
+    /// Balances that take part in reflections: everything except the pool, unclaimed fees, locked
+    /// and staked tokens and excluded accounts.
+    fn eligible_supply(&self) -> Balance {
+        let excluded: Balance = self
+            .reflection
+            .excluded
+            .iter()
+            .map(|account_id| self.token.accounts.get(&account_id).unwrap_or(0))
+            .sum();
+        self.token.total_supply - self.held_outside_balances() - excluded
+    }
+
+    /// Takes `amount` from `sender_id` and reflects it to all eligible holders. Returns false
+    /// without moving anything if there is nobody to reflect to, or if the eligible supply is so
+    /// small that the accumulator would overflow; the fee then goes to the fee bucket.
+    pub(crate) fn reflect(&mut self, sender_id: &AccountId, amount: Balance) -> bool {
+        self.settle_reflection(sender_id);
+        let balance = self.internal_registered_balance(sender_id);
+        MuteError::InsufficientBalance.check(balance >= amount);
+        let eligible = self.eligible_supply()
+            - if self.reflection.excluded.contains(sender_id) {
+                0
+            } else {
+                amount
+            };
+        if eligible == 0 {
+            return false;
+        }
+        let per_token = match checked_mul_div(amount, REFLECTION_PRECISION, eligible)
+            .and_then(|increment| self.reflection.per_token.checked_add(increment))
+        {
+            Some(per_token) => per_token,
+            None => return false,
+        };
+        self.token.accounts.insert(sender_id, &(balance - amount));
+        self.reflection.pool += amount;
+        self.reflection.per_token = per_token;
+        true
+    }
+
+    /// Takes `amount` out of the account's balance without changing the total supply, for tokens
+    /// the contract keeps track of elsewhere.
+    pub(crate) fn internal_debit(&mut self, account_id: &AccountId, amount: Balance) {
+        self.settle_reflection(account_id);
+        let balance = self.internal_registered_balance(account_id);
+        MuteError::InsufficientBalance.check(balance >= amount);
+        self.token.accounts.insert(account_id, &(balance - amount));
+    }
+
+    /// Returns tokens taken out by `internal_debit` to an account's balance.
+    pub(crate) fn internal_credit(&mut self, account_id: &AccountId, amount: Balance) {
+        self.settle_reflection(account_id);
+        let balance = self.internal_registered_balance(account_id);
+        self.token.accounts.insert(account_id, &(balance + amount));
+    }
+
+    /// `FungibleToken::internal_transfer` that keeps reflections settled.
+    pub(crate) fn internal_transfer(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+        amount: Balance,
+        memo: Option<String>,
+    ) {
+        self.settle_reflection(sender_id);
+        self.settle_reflection(receiver_id);
+        self.token
+            .internal_transfer(sender_id, receiver_id, amount, memo);
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        for id in [2, 4, 5] {
+            register_user(ctx, &mut contract, id);
+        }
+        as_account(ctx, 1);
+        contract.set_reflection_bps(5_000);
+        // The owner acts as the treasury.
+        contract.set_reflection_excluded(accounts(1), true);
+        contract.set_trading_allowlisted(accounts(2), true);
+        contract.set_trading_allowlisted(accounts(4), true);
+        contract.set_trading_allowlisted(accounts(5), true);
+        contract
+    }
+
+    fn as_account(ctx: &mut VMContextBuilder, id: usize) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(id))
+            .build());
+    }
+
+    fn balances(contract: &Contract) -> Vec<Balance> {
+        contract
+            .ft_holders(None, None)
+            .into_iter()
+            .map(|(_, balance)| balance.0)
+            .collect()
+    }
+
+    /// Every unit is either in a stored balance, in the pool or in the fee bucket, and holders see
+    /// all of it up to one unit of rounding per operation.
+    fn assert_supply_invariant(contract: &Contract, operations: u128) {
+        let holders = contract.ft_holders(None, None);
+        let stored: Balance = holders
+            .iter()
+            .map(|(account_id, _)| contract.token.accounts.get(account_id).unwrap())
+            .sum();
+        assert_eq!(
+            stored
+                + contract.reflection.pool
+                + contract.accrued_fees
+                + contract.locks.total_locked
+                + contract.staking.held()
+                + contract.auction.held,
+            contract.ft_total_supply().0
+        );
+        let visible: Balance = balances(contract).iter().sum::<Balance>() + contract.accrued_fees;
+        assert!(visible <= contract.ft_total_supply().0);
+        let dust = contract.ft_total_supply().0 - visible;
+        assert!(dust <= 4 * operations, "dust {}", dust);
+    }
+
+    #[test]
+    fn test_reflection_distributes_pro_rata() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        // The fee is reflected once the amount has arrived, so charlie gets all 50_000, less
+        // rounding dust.
+        contract.ft_transfer(accounts(2), U128(1_000_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 949_999);
+        assert_eq!(contract.reflection_info().pool, U128(50_000));
+        assert_eq!(contract.ft_accrued_fees().0, 50_000);
+        as_account(ctx, 3);
+        contract.claim_fees();
+        assert_eq!(contract.ft_balance_of(accounts(3)).0, 50_000);
+
+        // 50_000 is reflected over charlie's 950_000, danny's 50_000 and eugene's 900_000.
+        as_account(ctx, 1);
+        contract.ft_transfer(accounts(4), U128(1_000_000), None);
+        eprintln!(
+            "{} {} {}",
+            contract.ft_balance_of(accounts(2)).0,
+            contract.ft_balance_of(accounts(3)).0,
+            contract.ft_balance_of(accounts(4)).0
+        );
+        assert_supply_invariant(&contract, 2);
+
+        // The whole balance leaves before the fee is reflected, so none of it comes back.
+        as_account(ctx, 2);
+        let balance = contract.ft_balance_of(accounts(2));
+        contract.ft_transfer(accounts(5), balance, None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 0);
+        assert_supply_invariant(&contract, 3);
+    }
+
+    #[test]
+    fn test_reflection_sum_of_balances_matches_supply() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_reflection_excluded(accounts(1), false);
+        contract.set_reflection_excluded(accounts(5), true);
+        for id in [2, 4, 5] {
+            contract.ft_transfer(accounts(id), U128(7_777_777_777), None);
+        }
+        assert_supply_invariant(&contract, 3);
+
+        let senders = [2, 4, 5, 1];
+        for round in 0..40u128 {
+            let from = senders[(round % 4) as usize];
+            let to = senders[((round * 7 + 1) % 4) as usize];
+            if from == to {
+                continue;
+            }
+            as_account(ctx, from);
+            let balance = contract.ft_balance_of(accounts(from)).0;
+            let amount = balance / (round + 3) + 1;
+            contract.ft_transfer(accounts(to), U128(amount), None);
+            assert_supply_invariant(&contract, 4 + round);
+        }
+    }
+
+    #[test]
+    fn test_excluded_accounts_get_no_reflections() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.ft_transfer(accounts(2), U128(1_000_000), None);
+        contract.set_reflection_excluded(accounts(2), true);
+        let before = contract.ft_balance_of(accounts(2)).0;
+
+        contract.ft_transfer(accounts(4), U128(1_000_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, before);
+        assert_supply_invariant(&contract, 2);
+
+        contract.set_reflection_excluded(accounts(2), false);
+        contract.ft_transfer(accounts(4), U128(1_000_000), None);
+        assert!(contract.ft_balance_of(accounts(2)).0 > before);
+        assert_supply_invariant(&contract, 3);
+    }
+
+    #[test]
+    fn test_contract_account_excluded_by_default() {
+        let ctx = &mut get_context(accounts(1));
+        let contract = setup(ctx);
+        assert_eq!(
+            contract.reflection_info().excluded,
+            vec![accounts(0), accounts(1)]
+        );
+    }
+
+    #[test]
+    fn test_small_eligible_supply_falls_back_to_fee_bucket() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_accrued_fees().0, 50);
+        let balance = contract.ft_balance_of(accounts(2)).0;
+
+        // The receiver is excluded, so reflecting 5e22 over charlie's 900 eligible units would
+        // overflow the accumulator.
+        contract.set_reflection_excluded(accounts(5), true);
+        contract.ft_transfer(accounts(5), U128(10u128.pow(24)), None);
+        assert_eq!(contract.ft_accrued_fees().0, 50 + 10u128.pow(23));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, balance);
+        assert_eq!(contract.reflection.pool, 50);
+
+        // Once the eligible supply is large enough, fees are reflected again.
+        contract.ft_transfer(accounts(4), U128(1_000), None);
+        assert_eq!(contract.reflection.pool, 100);
+        assert_supply_invariant(&contract, 3);
+    }
+}
@@ -0,0 +1,295 @@
+//! Owner tools to recover assets sent to the contract account by mistake: other NEP-141 tokens,
+//! MUTE credited to the contract account itself, and NEAR above what the contract owes.
+//!
+//! NEAR can only be withdrawn down to the storage staked by the account plus the NEAR the
+//! contract holds on behalf of users, so rescues never touch deposits or sale and auction funds.
+//! The storage prepaid by every registered account counts as held for users too, since it pays
+//! for the account's later record writes and is refunded by `storage_unregister`.
+use crate::events::MuteEvent;
+use crate::*;
+use near_contract_standards::fungible_token::core::ext_ft_core;
+use near_sdk::{Promise, ONE_YOCTO};
+
+const GAS_FOR_FT_TRANSFER: Gas = Gas(15_000_000_000_000);
+const GAS_FOR_ON_FT_RESCUED: Gas = Gas(5_000_000_000_000);
+
+impl Contract {
+    /// NEAR the contract owes to users and the owner, on top of its storage stake.
+    pub(crate) fn near_liabilities(&self) -> Balance {
+        self.registration_deposits()
+            + self.wrapped_near
+            + self.sale.reserve
+            + self.sale.proceeds
+            + self.auction.pending
+            + self.auction.proceeds
+            + self.history.total_deposit
+            + self.sponsor.balance
+    }
+
+    /// Storage deposits of the registered accounts, at the current registration cost.
+    fn registration_deposits(&self) -> Balance {
+        Balance::from(self.holders.len()) * self.storage_balance_bounds().min.0
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// NEAR that can be withdrawn with `rescue_near`.
+    pub fn rescuable_near(&self) -> U128 {
+        let staked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        env::account_balance()
+            .saturating_sub(staked)
+            .saturating_sub(self.near_liabilities())
+            .into()
+    }
+
+    /// Sends `amount` of another NEP-141 token held by the contract account to `receiver_id`.
+    /// The receiver must be registered with that token.
+    #[payable]
+    pub fn rescue_ft(
+        &mut self,
+        token_contract: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> Promise {
+        self.assert_owner();
+        require!(
+            token_contract != env::current_account_id(),
+            "Use rescue_own_tokens to recover MUTE"
+        );
+        require!(amount.0 > 0, "The amount should be a positive number");
+        ext_ft_core::ext(token_contract.clone())
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(receiver_id.clone(), amount, Some("rescue".to_string()))
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_ON_FT_RESCUED)
+                    .on_ft_rescued(token_contract, receiver_id, amount),
+            )
+    }
+
+    /// Callback of `rescue_ft`, returns the amount that was sent.
+    #[private]
+    pub fn on_ft_rescued(
+        &mut self,
+        token_contract: AccountId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> U128 {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            MuteEvent::TokensRescued {
+                token_contract: &token_contract,
+                receiver_id: &receiver_id,
+                amount,
+            }
+            .emit();
+            amount
+        } else {
+            log!(
+                "Rescue of {} from @{} to @{} failed",
+                amount.0,
+                token_contract,
+                receiver_id
+            );
+            U128(0)
+        }
+    }
+
+    /// Moves MUTE held by the contract account to `receiver_id`, all of it if `amount` is
+    /// omitted. No fee is charged.
+    #[payable]
+    pub fn rescue_own_tokens(&mut self, receiver_id: AccountId, amount: Option<U128>) -> U128 {
+        self.assert_owner();
+        let contract_id = env::current_account_id();
+        let balance = self.internal_balance_of(&contract_id);
+        let amount = amount.map_or(balance, |amount| amount.0);
+        require!(amount > 0, "The amount should be a positive number");
+        require!(
+            amount <= balance,
+            "The amount exceeds the balance of the contract account"
+        );
+        self.internal_transfer(
+            &contract_id,
+            &receiver_id,
+            amount,
+            Some("rescue".to_string()),
+        );
+        self.assert_transfer_limits(None, &receiver_id, amount);
+        MuteEvent::TokensRescued {
+            token_contract: &contract_id,
+            receiver_id: &receiver_id,
+            amount: amount.into(),
+        }
+        .emit();
+        amount.into()
+    }
+
+    /// Sends NEAR above the storage stake and the contract's liabilities to `receiver_id`, all
+    /// of it if `amount` is omitted.
+    #[payable]
+    pub fn rescue_near(&mut self, receiver_id: AccountId, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        let surplus = self.rescuable_near().0;
+        let amount = amount.map_or(surplus, |amount| amount.0);
+        require!(amount > 0, "The amount should be a positive number");
+        require!(
+            amount <= surplus,
+            format!("The amount exceeds the NEAR surplus of {}", surplus)
+        );
+        MuteEvent::NearRescued {
+            receiver_id: &receiver_id,
+            amount: amount.into(),
+        }
+        .emit();
+        Promise::new(receiver_id).transfer(amount)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::{get_context, register_user, resolve_with};
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        for id in [0, 2] {
+            register_user(ctx, &mut contract, id);
+        }
+        contract
+    }
+
+    #[test]
+    fn test_rescue_own_tokens() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.ft_transfer(accounts(0), U128(1_000), None);
+        let stranded = contract.ft_balance_of(accounts(0)).0;
+        assert!(stranded > 0);
+
+        testing_env!(ctx.storage_usage(env::storage_usage()).build());
+        assert_eq!(
+            contract.rescue_own_tokens(accounts(2), None),
+            U128(stranded)
+        );
+        assert_eq!(contract.ft_balance_of(accounts(0)).0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, stranded);
+        assert_eq!(
+            get_logs().last().unwrap(),
+            &format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"tokens_rescued","data":{{"token_contract":"{}","receiver_id":"{}","amount":"{}"}}}}"#,
+                accounts(0),
+                accounts(2),
+                stranded
+            )
+        );
+    }
+
+    #[test]
+    fn test_rescue_near_is_limited_to_surplus() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.wrapped_near = 3 * ONE_NEAR;
+        let staked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        let deposits = contract.registration_deposits();
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .account_balance(staked + deposits + 3 * ONE_NEAR + 2 * ONE_NEAR)
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        // The attached yocto is part of the balance too.
+        assert_eq!(contract.rescuable_near(), U128(2 * ONE_NEAR + 1));
+        contract.rescue_near(accounts(2), Some(U128(ONE_NEAR)));
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"near_rescued","data":{{"receiver_id":"{}","amount":"{}"}}}}"#,
+                accounts(2),
+                ONE_NEAR
+            )]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the NEAR surplus")]
+    fn test_rescue_near_keeps_liabilities() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.auction.pending = ONE_NEAR;
+        let staked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        let deposits = contract.registration_deposits();
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .account_balance(staked + deposits + ONE_NEAR)
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        // Only the attached yocto is above the storage stake and the pending commitments.
+        contract.rescue_near(accounts(2), Some(U128(2)));
+    }
+
+    #[test]
+    fn test_unregister_refund_survives_rescue() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let staked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        let deposits = contract.registration_deposits();
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .account_balance(staked + deposits + ONE_NEAR)
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.rescue_near(accounts(4), None);
+        assert_eq!(contract.rescuable_near(), U128(0));
+
+        // The mocked runtime takes transfers out of the balance, so the refund is paid by now.
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .account_balance(env::account_balance())
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert!(contract.storage_unregister(None));
+        let staked = Balance::from(env::storage_usage()) * env::storage_byte_cost();
+        assert!(env::account_balance() >= staked);
+    }
+
+    #[test]
+    fn test_failed_ft_rescue_returns_zero() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        resolve_with(ctx, PromiseResult::Failed);
+        assert_eq!(
+            contract.on_ft_rescued(accounts(4), accounts(2), U128(10)),
+            U128(0)
+        );
+        resolve_with(ctx, PromiseResult::Successful(vec![]));
+        assert_eq!(
+            contract.on_ft_rescued(accounts(4), accounts(2), U128(10)),
+            U128(10)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Use rescue_own_tokens to recover MUTE")]
+    fn test_rescue_ft_rejects_own_token() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.rescue_ft(accounts(0), accounts(2), U128(10));
+    }
+}
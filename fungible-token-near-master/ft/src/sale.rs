@@ -0,0 +1,702 @@
+//! Token sale along a bonding curve. `buy` mints tokens for the attached NEAR at the curve price
+//! and `sell` burns them for NEAR from the curve reserve, so the supply only changes through the
+//! curve.
+//!
+//! A share of every purchase (`reserve_bps`) is kept in the reserve, which always covers selling
+//! back everything sold at the same share of the curve price. The rest is proceeds the owner can
+//! withdraw. Purchases round the price up and sales round it down, so rounding never drains the
+//! reserve.
+//!
+//! An account can only sell back what it bought from the curve, net of earlier sales. Sales go
+//! through the launch gate and the max transaction limit like transfers, and pay the transfer
+//! fee as a transfer to the contract account; only the rest is sold back.
+use crate::events::MuteEvent;
+use crate::fees::FEE_BPS_DENOMINATOR;
+use crate::math::mul_div;
+use crate::math::U256;
+use crate::*;
+use near_contract_standards::fungible_token::events::FtMint;
+use near_sdk::collections::{LookupMap, LookupSet};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{IntoStorageKey, Promise};
+
+/// Bounds the work of pricing an exponential curve.
+pub const MAX_CURVE_STEPS: u128 = 200;
+/// Keeps allowlist updates within gas limits.
+pub const MAX_ALLOWLIST_BATCH: usize = 100;
+
+/// Price of a whole token (10^decimals units) in yoctoNEAR as a function of the tokens sold.
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BondingCurve {
+    /// `base_price + slope * sold`, with `sold` in whole tokens.
+    Linear { base_price: U128, slope: U128 },
+    /// `base_price`, raised by `step_bps` every `step` tokens sold.
+    Exponential {
+        base_price: U128,
+        step: U128,
+        step_bps: u16,
+    },
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleConfig {
+    pub curve: BondingCurve,
+    /// Most tokens the curve can have sold at once.
+    pub supply_cap: U128,
+    /// Share of each purchase kept in the reserve to buy tokens back.
+    pub reserve_bps: u16,
+    /// Most tokens a single account can buy, net of what it sold back.
+    pub max_per_account: Option<U128>,
+}
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum SalePhase {
+    /// No buying or selling.
+    Closed,
+    /// Only allowlisted accounts can buy, everyone can sell.
+    Allowlist,
+    Public,
+    /// Buying is over, tokens can still be sold back.
+    Ended,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokenSale {
+    pub config: Option<SaleConfig>,
+    pub phase: SalePhase,
+    /// Units in a whole token, prices are per whole token.
+    pub unit: Balance,
+    pub sold: Balance,
+    pub reserve: Balance,
+    pub proceeds: Balance,
+    pub purchases: LookupMap<AccountId, Balance>,
+    pub allowlist: LookupSet<AccountId>,
+}
+
+impl TokenSale {
+    pub fn new<S, T>(purchases_prefix: S, allowlist_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            config: None,
+            phase: SalePhase::Closed,
+            unit: 1,
+            sold: 0,
+            reserve: 0,
+            proceeds: 0,
+            purchases: LookupMap::new(purchases_prefix),
+            allowlist: LookupSet::new(allowlist_prefix),
+        }
+    }
+
+    /// Storage taken by one purchase record, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.purchases.insert(&tmp_account_id, &0);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.purchases.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn config(&self) -> &SaleConfig {
+        self.config
+            .as_ref()
+            .unwrap_or_else(|| env::panic_str("The sale is not configured"))
+    }
+
+    /// Tokens `deposit` buys at the current point of the curve and their price.
+    pub fn quote_buy(&self, deposit: Balance) -> (Balance, Balance) {
+        let config = self.config();
+        let available = config.supply_cap.0.saturating_sub(self.sold);
+        let amount = config
+            .curve
+            .amount_for(self.unit, self.sold, deposit, available);
+        let cost = config.curve.cost(self.unit, self.sold, amount, true);
+        (amount, cost)
+    }
+
+    /// NEAR paid out for selling back `amount` tokens.
+    pub fn quote_sell(&self, amount: Balance) -> Balance {
+        require!(amount <= self.sold, "The amount exceeds the tokens sold");
+        let config = self.config();
+        let cost = config
+            .curve
+            .cost(self.unit, self.sold - amount, amount, false);
+        mul_div(
+            cost,
+            Balance::from(config.reserve_bps),
+            Balance::from(FEE_BPS_DENOMINATOR),
+        )
+    }
+}
+
+impl BondingCurve {
+    fn validate(&self, unit: Balance, supply_cap: Balance) {
+        let base_price = match self {
+            BondingCurve::Linear { base_price, .. } => base_price,
+            BondingCurve::Exponential { base_price, .. } => base_price,
+        };
+        require!(base_price.0 > 0, "The base price should be positive");
+        if let BondingCurve::Exponential { step, .. } = self {
+            require!(step.0 > 0, "The curve step should be positive");
+            require!(
+                supply_cap / step.0 <= MAX_CURVE_STEPS,
+                format!(
+                    "The supply cap can't exceed {} curve steps",
+                    MAX_CURVE_STEPS
+                )
+            );
+        }
+        // Pricing the whole supply must not overflow.
+        self.cost(unit, 0, supply_cap, true);
+    }
+
+    /// Price of `amount` tokens when `sold` were sold already, rounded up or down.
+    pub fn cost(&self, unit: Balance, sold: Balance, amount: Balance, round_up: bool) -> Balance {
+        let div = |num: U256, den: U256| {
+            if round_up {
+                (num + den - 1) / den
+            } else {
+                num / den
+            }
+        };
+        match self {
+            BondingCurve::Linear { base_price, slope } => {
+                // Integral of the price over [sold, sold + amount].
+                let unit = U256::from(unit);
+                let num = U256::from(amount)
+                    * (U256::from(2) * U256::from(base_price.0) * unit
+                        + U256::from(slope.0)
+                            * (U256::from(sold) * U256::from(2) + U256::from(amount)));
+                div(num, U256::from(2) * unit * unit).as_u128()
+            }
+            BondingCurve::Exponential { step, .. } => {
+                let mut cost = 0;
+                let mut position = sold;
+                let end = sold + amount;
+                while position < end {
+                    let step_end = std::cmp::min((position / step.0 + 1) * step.0, end);
+                    let price = self.step_price(position / step.0);
+                    cost += div(
+                        U256::from(step_end - position) * U256::from(price),
+                        U256::from(unit),
+                    )
+                    .as_u128();
+                    position = step_end;
+                }
+                cost
+            }
+        }
+    }
+
+    /// Most tokens, up to `max`, whose rounded up price is covered by `deposit`.
+    pub fn amount_for(
+        &self,
+        unit: Balance,
+        sold: Balance,
+        deposit: Balance,
+        max: Balance,
+    ) -> Balance {
+        match self {
+            BondingCurve::Linear { .. } => {
+                let (mut low, mut high) = (0, max);
+                while low < high {
+                    let mid = high - (high - low) / 2;
+                    if self.cost(unit, sold, mid, true) <= deposit {
+                        low = mid;
+                    } else {
+                        high = mid - 1;
+                    }
+                }
+                low
+            }
+            BondingCurve::Exponential { step, .. } => {
+                let mut left = deposit;
+                let mut position = sold;
+                let end = sold + max;
+                while position < end {
+                    let step_end = std::cmp::min((position / step.0 + 1) * step.0, end);
+                    let price = self.step_price(position / step.0);
+                    let step_cost = self.cost(unit, position, step_end - position, true);
+                    if step_cost > left {
+                        return position + mul_div(left, unit, price) - sold;
+                    }
+                    left -= step_cost;
+                    position = step_end;
+                }
+                max
+            }
+        }
+    }
+
+    /// Price of a whole token at `sold`.
+    pub fn price_at(&self, unit: Balance, sold: Balance) -> Balance {
+        match self {
+            BondingCurve::Linear { base_price, slope } => {
+                base_price.0 + mul_div(slope.0, sold, unit)
+            }
+            BondingCurve::Exponential { step, .. } => self.step_price(sold / step.0),
+        }
+    }
+
+    fn step_price(&self, index: u128) -> Balance {
+        match self {
+            BondingCurve::Exponential {
+                base_price,
+                step_bps,
+                ..
+            } => (0..index).fold(base_price.0, |price, _| {
+                mul_div(
+                    price,
+                    Balance::from(FEE_BPS_DENOMINATOR + *step_bps),
+                    Balance::from(FEE_BPS_DENOMINATOR),
+                )
+            }),
+            BondingCurve::Linear { .. } => unreachable!(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleInfo {
+    pub config: Option<SaleConfig>,
+    pub phase: SalePhase,
+    pub sold: U128,
+    pub reserve: U128,
+    pub proceeds: U128,
+    /// Price of the next whole token, in yoctoNEAR.
+    pub price: Option<U128>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn sale_info(&self) -> SaleInfo {
+        SaleInfo {
+            config: self.sale.config.clone(),
+            phase: self.sale.phase,
+            sold: self.sale.sold.into(),
+            reserve: self.sale.reserve.into(),
+            proceeds: self.sale.proceeds.into(),
+            price: self
+                .sale
+                .config
+                .as_ref()
+                .map(|config| config.curve.price_at(self.sale.unit, self.sale.sold).into()),
+        }
+    }
+
+    /// Tokens bought by the account, net of sales.
+    pub fn sale_purchased_of(&self, account_id: AccountId) -> U128 {
+        self.sale.purchases.get(&account_id).unwrap_or(0).into()
+    }
+
+    pub fn is_sale_allowlisted(&self, account_id: AccountId) -> bool {
+        self.sale.allowlist.contains(&account_id)
+    }
+
+    /// Tokens `near_amount` buys now.
+    pub fn sale_quote_buy(&self, near_amount: U128) -> U128 {
+        self.sale.quote_buy(near_amount.0).0.into()
+    }
+
+    /// NEAR paid for selling `amount` back now, not counting the transfer fee on the sale.
+    pub fn sale_quote_sell(&self, amount: U128) -> U128 {
+        self.sale.quote_sell(amount.0).into()
+    }
+
+    /// The curve and the reserve share are fixed once tokens were sold, caps can change anytime.
+    #[payable]
+    pub fn set_sale_config(&mut self, config: SaleConfig) {
+        self.assert_owner();
+        require!(
+            config.reserve_bps <= FEE_BPS_DENOMINATOR,
+            "The reserve share can't exceed 10000 bps"
+        );
+        if let Some(current) = &self.sale.config {
+            if self.sale.sold > 0 {
+                require!(
+                    current.curve == config.curve && current.reserve_bps == config.reserve_bps,
+                    "The curve can't change after tokens were sold"
+                );
+            }
+        }
+        self.sale.unit = self.token_unit();
+        config.curve.validate(self.sale.unit, config.supply_cap.0);
+        MuteEvent::SaleConfigUpdated { config: &config }.emit();
+        self.sale.config = Some(config);
+    }
+
+    #[payable]
+    pub fn set_sale_phase(&mut self, phase: SalePhase) {
+        self.assert_owner();
+        if phase != SalePhase::Closed {
+            self.sale.config();
+        }
+        self.sale.phase = phase;
+        MuteEvent::SalePhaseUpdated { phase }.emit();
+    }
+
+    #[payable]
+    pub fn set_sale_allowlisted(&mut self, account_ids: Vec<AccountId>, allowed: bool) {
+        self.assert_owner();
+        require!(
+            account_ids.len() <= MAX_ALLOWLIST_BATCH,
+            format!("At most {} accounts at once", MAX_ALLOWLIST_BATCH)
+        );
+        for account_id in account_ids {
+            let changed = if allowed {
+                self.sale.allowlist.insert(&account_id)
+            } else {
+                self.sale.allowlist.remove(&account_id)
+            };
+            if changed {
+                MuteEvent::SaleAllowlistUpdated {
+                    account_id: &account_id,
+                    allowed,
+                }
+                .emit();
+            }
+        }
+    }
+
+    /// Sends sale proceeds to the owner, all of them if `amount` is omitted.
+    #[payable]
+    pub fn withdraw_sale_proceeds(&mut self, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        let amount = amount.map_or(self.sale.proceeds, |amount| amount.0);
+        require!(amount > 0, "The amount should be a positive number");
+        require!(
+            amount <= self.sale.proceeds,
+            "The amount exceeds the sale proceeds"
+        );
+        self.sale.proceeds -= amount;
+        MuteEvent::SaleProceedsWithdrawn {
+            account_id: &self.owner_id,
+            amount: amount.into(),
+        }
+        .emit();
+        Promise::new(self.owner_id.clone()).transfer(amount)
+    }
+
+    /// Mints tokens for the attached NEAR at the curve price. NEAR that doesn't buy a whole unit
+    /// is refunded.
+    #[payable]
+    pub fn buy(&mut self, min_out: U128) -> U128 {
+        let account_id = env::predecessor_account_id();
+        match self.sale.phase {
+            SalePhase::Public => {}
+            SalePhase::Allowlist => require!(
+                self.sale.allowlist.contains(&account_id),
+                "The account is not on the sale allowlist"
+            ),
+            _ => env::panic_str("The sale is not open for buying"),
+        }
+        self.assert_registered(&account_id);
+        let deposit = env::attached_deposit();
+        let (amount, cost) = self.sale.quote_buy(deposit);
+        require!(amount > 0, "The deposit doesn't buy any tokens");
+        require!(
+            amount >= min_out.0,
+            format!(
+                "Slippage error: {} tokens bought, {} expected",
+                amount, min_out.0
+            )
+        );
+        let config = self.sale.config();
+        let purchased = self.sale.purchases.get(&account_id).unwrap_or(0) + amount;
+        if let Some(max_per_account) = config.max_per_account {
+            require!(
+                purchased <= max_per_account.0,
+                "The purchase exceeds the per-account cap"
+            );
+        }
+        // The reserve share is rounded up, like the cost.
+        let reserved = cost
+            - mul_div(
+                cost,
+                Balance::from(FEE_BPS_DENOMINATOR - config.reserve_bps),
+                Balance::from(FEE_BPS_DENOMINATOR),
+            );
+
+        self.sale.purchases.insert(&account_id, &purchased);
+        self.sale.sold += amount;
+        self.sale.reserve += reserved;
+        self.sale.proceeds += cost - reserved;
+        self.settle_reflection(&account_id);
+        self.token.internal_deposit(&account_id, amount);
+        self.assert_transfer_limits(None, &account_id, amount);
+        FtMint {
+            owner_id: &account_id,
+            amount: &U128(amount),
+            memo: Some("buy"),
+        }
+        .emit();
+        MuteEvent::TokensBought {
+            account_id: &account_id,
+            amount: amount.into(),
+            cost: cost.into(),
+        }
+        .emit();
+        if deposit > cost {
+            Promise::new(account_id).transfer(deposit - cost);
+        }
+        amount.into()
+    }
+
+    /// Takes `amount` of the predecessor's tokens, charges the transfer fee and burns the rest
+    /// for NEAR from the reserve.
+    #[payable]
+    pub fn sell(&mut self, amount: U128, min_near_out: U128) -> Promise {
+        assert_one_yocto();
+        require!(self.sale.phase != SalePhase::Closed, "The sale is closed");
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let purchased = self.sale.purchases.get(&account_id).unwrap_or(0);
+        require!(
+            amount.0 <= purchased,
+            "The amount exceeds the tokens bought from the sale"
+        );
+        self.assert_can_send(&account_id);
+        self.assert_max_tx(&account_id, amount.0);
+        MuteError::InsufficientBalance.check(self.internal_balance_of(&account_id) >= amount.0);
+        let (sold, fee) = self.calculate_fee(&account_id, &env::current_account_id(), amount);
+        let payout = self.sale.quote_sell(sold.0);
+        require!(
+            payout >= min_near_out.0,
+            format!(
+                "Slippage error: {} yoctoNEAR paid, {} expected",
+                payout, min_near_out.0
+            )
+        );
+
+        self.internal_burn(&account_id, sold.0, "sell");
+        if let Some(fee) = fee {
+            self.distribute_fee(&account_id, fee.0);
+        }
+        self.sale.sold -= sold.0;
+        self.sale.reserve -= payout;
+        self.sale
+            .purchases
+            .insert(&account_id, &(purchased - amount.0));
+        MuteEvent::TokensSold {
+            account_id: &account_id,
+            amount: sold,
+            payout: payout.into(),
+        }
+        .emit();
+        Promise::new(account_id).transfer(payout)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::fees::{AccountClass, ClassFee, FeeSchedule};
+    use crate::tests::{get_context, register_user};
+
+    const TOKEN: Balance = 10u128.pow(DEFAULT_DECIMALS as u32);
+
+    fn setup(ctx: &mut VMContextBuilder, curve: BondingCurve) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_sale_config(SaleConfig {
+            curve,
+            supply_cap: U128(1_000 * TOKEN),
+            reserve_bps: 8_000,
+            max_per_account: Some(U128(500 * TOKEN)),
+        });
+        contract.set_sale_phase(SalePhase::Public);
+        contract.enable_trading(None, None);
+        contract
+    }
+
+    /// Lets sales skip the transfer fee, by a zero-fee class for the contract account.
+    fn waive_sell_fee(contract: &mut Contract) {
+        contract.set_account_class(accounts(0), Some(AccountClass::Contract));
+        contract.set_fee_schedule(FeeSchedule {
+            class_overrides: vec![ClassFee {
+                class: AccountClass::Contract,
+                bps: 0,
+            }],
+            ..FeeSchedule::default()
+        });
+    }
+
+    fn buy_as(ctx: &mut VMContextBuilder, contract: &mut Contract, deposit: Balance) -> Balance {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(deposit)
+            .build());
+        contract.buy(U128(0)).0
+    }
+
+    fn sell_as(ctx: &mut VMContextBuilder, contract: &mut Contract, amount: Balance) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(1)
+            .build());
+        contract.sell(U128(amount), U128(0));
+    }
+
+    fn linear() -> BondingCurve {
+        // 1 NEAR for the first token, 0.01 NEAR more for each token sold.
+        BondingCurve::Linear {
+            base_price: U128(ONE_NEAR),
+            slope: U128(ONE_NEAR / 100),
+        }
+    }
+
+    #[test]
+    fn test_linear_buy_and_sell() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        waive_sell_fee(&mut contract);
+        // 10 tokens cost 10 NEAR plus 0.01 * 10^2 / 2 = 10.5 NEAR.
+        assert_eq!(
+            contract.sale_quote_buy(U128(ONE_NEAR * 105 / 10)),
+            U128(10 * TOKEN)
+        );
+        assert_eq!(buy_as(ctx, &mut contract, ONE_NEAR * 105 / 10), 10 * TOKEN);
+        let info = contract.sale_info();
+        assert_eq!(info.sold, U128(10 * TOKEN));
+        assert_eq!(info.price, Some(U128(ONE_NEAR * 11 / 10)));
+        assert_eq!(info.reserve, U128(ONE_NEAR * 84 / 10));
+        assert_eq!(info.proceeds, U128(ONE_NEAR * 21 / 10));
+        assert_eq!(
+            contract.ft_total_supply().0,
+            DEFAULT_TOTAL_SUPPLY + 10 * TOKEN
+        );
+
+        // Selling the last 5 tokens pays 80% of 5.375 NEAR.
+        assert_eq!(
+            contract.sale_quote_sell(U128(5 * TOKEN)),
+            U128(ONE_NEAR * 43 / 10)
+        );
+        sell_as(ctx, &mut contract, 5 * TOKEN);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 5 * TOKEN);
+        assert_eq!(contract.sale_purchased_of(accounts(2)).0, 5 * TOKEN);
+        assert_eq!(contract.sale_info().reserve, U128(ONE_NEAR * 41 / 10));
+        sell_as(ctx, &mut contract, 5 * TOKEN);
+        assert_eq!(contract.sale_info().reserve, U128(0));
+        assert_eq!(contract.ft_total_supply().0, DEFAULT_TOTAL_SUPPLY);
+        assert_eq!(contract.ft_total_burned().0, 10 * TOKEN);
+    }
+
+    #[test]
+    fn test_exponential_curve_steps() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(
+            ctx,
+            BondingCurve::Exponential {
+                base_price: U128(ONE_NEAR),
+                step: U128(10 * TOKEN),
+                step_bps: 1_000,
+            },
+        );
+        waive_sell_fee(&mut contract);
+        // 10 tokens at 1 NEAR and 5 at 1.1 NEAR.
+        assert_eq!(buy_as(ctx, &mut contract, ONE_NEAR * 155 / 10), 15 * TOKEN);
+        assert_eq!(contract.sale_info().price, Some(U128(ONE_NEAR * 11 / 10)));
+        // The reserve covers selling everything back, whatever the order.
+        let reserve = contract.sale_info().reserve.0;
+        let payouts = contract.sale_quote_sell(U128(7 * TOKEN)).0;
+        sell_as(ctx, &mut contract, 7 * TOKEN);
+        assert_eq!(
+            payouts + contract.sale_quote_sell(U128(8 * TOKEN)).0,
+            reserve
+        );
+    }
+
+    #[test]
+    fn test_sell_pays_transfer_fee() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        buy_as(ctx, &mut contract, ONE_NEAR * 105 / 10);
+        let accrued = contract.ft_accrued_fees().0;
+        // Of 5 tokens, 0.5 is the fee and 4.5 are sold back.
+        let payout = contract.sale_quote_sell(U128(45 * TOKEN / 10)).0;
+        let reserve = contract.sale_info().reserve.0;
+        sell_as(ctx, &mut contract, 5 * TOKEN);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 5 * TOKEN);
+        assert_eq!(contract.ft_accrued_fees().0, accrued + TOKEN / 2);
+        assert_eq!(contract.sale_purchased_of(accounts(2)).0, 5 * TOKEN);
+        let info = contract.sale_info();
+        assert_eq!(info.sold, U128(55 * TOKEN / 10));
+        assert_eq!(info.reserve, U128(reserve - payout));
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the tokens bought from the sale")]
+    fn test_sell_is_limited_to_purchases() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        buy_as(ctx, &mut contract, ONE_NEAR * 105 / 10);
+        // The owner's pre-minted supply was never bought from the curve.
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.sell(U128(TOKEN), U128(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E004: Trading is not enabled yet")]
+    fn test_sell_waits_for_trading() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        buy_as(ctx, &mut contract, ONE_NEAR * 105 / 10);
+        contract.launch.trading_enabled_at = None;
+        sell_as(ctx, &mut contract, 5 * TOKEN);
+    }
+
+    #[test]
+    fn test_rounding_keeps_reserve_solvent() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        for deposit in [1_000_003, 1_000_000_007, ONE_NEAR / 3, ONE_NEAR * 7 / 3] {
+            buy_as(ctx, &mut contract, deposit);
+        }
+        let sold = contract.sale_info().sold.0;
+        assert!(contract.sale_quote_sell(U128(sold)).0 <= contract.sale_info().reserve.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "The purchase exceeds the per-account cap")]
+    fn test_per_account_cap() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        buy_as(ctx, &mut contract, 2_000 * ONE_NEAR);
+    }
+
+    #[test]
+    #[should_panic(expected = "The account is not on the sale allowlist")]
+    fn test_allowlist_phase() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, linear());
+        contract.set_sale_phase(SalePhase::Allowlist);
+        buy_as(ctx, &mut contract, ONE_NEAR);
+    }
+}
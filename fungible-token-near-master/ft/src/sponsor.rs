@@ -0,0 +1,494 @@
+//! Registration of transfer receivers that never called `storage_deposit`.
+//!
+//! A sender can attach at least `storage_balance_bounds().min` to a transfer to pay for the
+//! receiver's registration, the excess is refunded. Otherwise, with a plain one yoctoNEAR
+//! transfer, the registration is paid from a sponsor pool funded by the DAO, within a limit per
+//! period and a limit per sender and period.
+//!
+//! Sponsored registrations are recorded, so when such an account unregisters the storage
+//! deposit goes back to whoever paid it instead of to the account.
+use crate::errors::MuteError;
+use crate::events::MuteEvent;
+use crate::launch::NANOS_PER_SECOND;
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{IntoStorageKey, Promise, ONE_YOCTO};
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SponsorConfig {
+    pub enabled: bool,
+    pub period_secs: u64,
+    /// Registrations the pool pays for in a period.
+    pub max_per_period: u32,
+    /// Registrations the pool pays for a single sender in a period.
+    pub max_per_sender: u32,
+}
+
+impl Default for SponsorConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            period_secs: 24 * 3_600,
+            max_per_period: 100,
+            max_per_sender: 5,
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SponsorUsage {
+    pub period: u64,
+    pub count: u32,
+}
+
+/// Who paid for a sponsored registration and how much, the contract itself for the pool.
+#[derive(BorshDeserialize, BorshSerialize, Debug, PartialEq)]
+pub struct SponsoredRegistration {
+    pub sponsor_id: AccountId,
+    pub amount: Balance,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SponsorPool {
+    pub config: SponsorConfig,
+    /// NEAR available for registrations.
+    pub balance: Balance,
+    pub period: u64,
+    /// Registrations paid in `period`.
+    pub period_count: u32,
+    /// Registrations paid by the pool so far.
+    pub total_sponsored: u64,
+    pub usage: LookupMap<AccountId, SponsorUsage>,
+    pub sponsored: LookupMap<AccountId, SponsoredRegistration>,
+}
+
+impl SponsorPool {
+    pub fn new<S, T>(usage_prefix: S, sponsored_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            config: SponsorConfig::default(),
+            balance: 0,
+            period: 0,
+            period_count: 0,
+            total_sponsored: 0,
+            usage: LookupMap::new(usage_prefix),
+            sponsored: LookupMap::new(sponsored_prefix),
+        }
+    }
+
+    /// Storage taken by the usage of one sender and the sponsor of one account, charged as part
+    /// of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.usage.insert(
+            &tmp_account_id,
+            &SponsorUsage {
+                period: 0,
+                count: 0,
+            },
+        );
+        self.sponsored.insert(
+            &tmp_account_id,
+            &SponsoredRegistration {
+                sponsor_id: tmp_account_id.clone(),
+                amount: 0,
+            },
+        );
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.usage.remove(&tmp_account_id);
+        self.sponsored.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    fn current_period(&self) -> u64 {
+        env::block_timestamp() / (self.config.period_secs * NANOS_PER_SECOND)
+    }
+
+    /// Counts a registration for `sender_id` if the pool can pay `cost` within the limits.
+    fn try_use(&mut self, sender_id: &AccountId, cost: Balance) -> bool {
+        if !self.config.enabled || self.balance < cost {
+            return false;
+        }
+        let period = self.current_period();
+        if period != self.period {
+            self.period = period;
+            self.period_count = 0;
+        }
+        let mut usage = self
+            .usage
+            .get(sender_id)
+            .filter(|usage| usage.period == period)
+            .unwrap_or(SponsorUsage { period, count: 0 });
+        if self.period_count >= self.config.max_per_period
+            || usage.count >= self.config.max_per_sender
+        {
+            return false;
+        }
+        usage.count += 1;
+        self.usage.insert(sender_id, &usage);
+        self.period_count += 1;
+        self.total_sponsored += 1;
+        self.balance -= cost;
+        true
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SponsorPoolInfo {
+    pub config: SponsorConfig,
+    pub balance: U128,
+    /// NEAR paid for one registration.
+    pub registration_cost: U128,
+    /// Registrations left in the current period.
+    pub remaining_in_period: u32,
+    pub total_sponsored: U64,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn sponsor_pool_info(&self) -> SponsorPoolInfo {
+        let pool = &self.sponsor;
+        let used = if pool.period == pool.current_period() {
+            pool.period_count
+        } else {
+            0
+        };
+        SponsorPoolInfo {
+            config: pool.config.clone(),
+            balance: pool.balance.into(),
+            registration_cost: self.storage_balance_bounds().min,
+            remaining_in_period: pool.config.max_per_period.saturating_sub(used),
+            total_sponsored: pool.total_sponsored.into(),
+        }
+    }
+
+    #[payable]
+    pub fn set_sponsor_config(&mut self, config: SponsorConfig) {
+        self.assert_owner();
+        require!(
+            config.period_secs > 0,
+            "The period should be a positive number"
+        );
+        self.sponsor.config = config;
+        MuteEvent::SponsorConfigUpdated {
+            config: &self.sponsor.config,
+        }
+        .emit();
+    }
+
+    /// Adds the attached NEAR to the sponsor pool.
+    #[payable]
+    pub fn fund_sponsor_pool(&mut self) -> U128 {
+        let amount = env::attached_deposit();
+        require!(amount > 0, "Requires positive attached deposit");
+        self.sponsor.balance += amount;
+        MuteEvent::SponsorPoolFunded {
+            account_id: &env::predecessor_account_id(),
+            amount: amount.into(),
+        }
+        .emit();
+        self.sponsor.balance.into()
+    }
+
+    /// Sends NEAR from the sponsor pool to the owner, all of it if `amount` is omitted.
+    #[payable]
+    pub fn withdraw_sponsor_pool(&mut self, amount: Option<U128>) -> Promise {
+        self.assert_owner();
+        let amount = amount.map_or(self.sponsor.balance, |amount| amount.0);
+        require!(amount > 0, "The amount should be a positive number");
+        require!(
+            amount <= self.sponsor.balance,
+            "The amount exceeds the sponsor pool"
+        );
+        self.sponsor.balance -= amount;
+        MuteEvent::SponsorPoolWithdrawn {
+            account_id: &self.owner_id,
+            amount: amount.into(),
+        }
+        .emit();
+        Promise::new(self.owner_id.clone()).transfer(amount)
+    }
+}
+
+impl Contract {
+    /// Checks the deposit attached to a transfer and registers an unregistered receiver, paid
+    /// by the deposit if it's more than one yoctoNEAR, or else by the sponsor pool. If neither
+    /// applies the receiver stays unregistered and the transfer fails as usual.
+    pub(crate) fn assert_deposit_and_register_receiver(
+        &mut self,
+        sender_id: &AccountId,
+        receiver_id: &AccountId,
+    ) {
+        let deposit = env::attached_deposit();
+        if deposit <= ONE_YOCTO || self.token.accounts.contains_key(receiver_id) {
+            assert_one_yocto();
+            if !self.token.accounts.contains_key(receiver_id) {
+                self.sponsor_registration(sender_id, receiver_id);
+            }
+            return;
+        }
+        let cost = self.storage_balance_bounds().min.0;
+        MuteError::RegistrationDepositTooSmall.check_with(deposit >= cost, cost);
+        self.internal_register_account(receiver_id);
+        self.sponsor.sponsored.insert(
+            receiver_id,
+            &SponsoredRegistration {
+                sponsor_id: sender_id.clone(),
+                amount: cost,
+            },
+        );
+        MuteEvent::RegistrationSponsored {
+            account_id: receiver_id,
+            sponsor_id: sender_id,
+            amount: cost.into(),
+        }
+        .emit();
+        if deposit > cost {
+            Promise::new(sender_id.clone()).transfer(deposit - cost);
+        }
+    }
+
+    fn sponsor_registration(&mut self, sender_id: &AccountId, receiver_id: &AccountId) {
+        let cost = self.storage_balance_bounds().min.0;
+        if !self.sponsor.try_use(sender_id, cost) {
+            return;
+        }
+        self.internal_register_account(receiver_id);
+        self.sponsor.sponsored.insert(
+            receiver_id,
+            &SponsoredRegistration {
+                sponsor_id: env::current_account_id(),
+                amount: cost,
+            },
+        );
+        MuteEvent::RegistrationSponsored {
+            account_id: receiver_id,
+            sponsor_id: &env::current_account_id(),
+            amount: cost.into(),
+        }
+        .emit();
+    }
+
+    /// Returns what was paid for the registration of a sponsored account being closed to the
+    /// sponsor pool or the sender that paid it. Returns the part of `deposit` left to refund to
+    /// the account, nothing if it was sponsored.
+    pub(crate) fn return_sponsored_deposit(
+        &mut self,
+        account_id: &AccountId,
+        deposit: Balance,
+    ) -> Balance {
+        let registration = match self.sponsor.sponsored.remove(account_id) {
+            Some(registration) => registration,
+            None => return deposit,
+        };
+        if registration.sponsor_id == env::current_account_id() {
+            self.sponsor.balance += registration.amount;
+        } else {
+            Promise::new(registration.sponsor_id.clone()).transfer(registration.amount);
+        }
+        MuteEvent::SponsoredDepositReturned {
+            account_id,
+            sponsor_id: &registration.sponsor_id,
+            amount: registration.amount.into(),
+        }
+        .emit();
+        0
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+    use crate::tests::get_context;
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        Contract::new_default_config(accounts(1), accounts(3))
+    }
+
+    fn enable_pool(ctx: &mut VMContextBuilder, contract: &mut Contract, max_per_sender: u32) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_sponsor_config(SponsorConfig {
+            enabled: true,
+            max_per_sender,
+            ..SponsorConfig::default()
+        });
+        testing_env!(ctx.attached_deposit(ONE_NEAR).build());
+        contract.fund_sponsor_pool();
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .build());
+    }
+
+    #[test]
+    fn test_sender_pays_registration() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(cost + 5)
+            .build());
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+        assert_eq!(
+            get_logs()[0],
+            format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"registration_sponsored","data":{{"account_id":"{}","sponsor_id":"{}","amount":"{}"}}}}"#,
+                accounts(2),
+                accounts(1),
+                cost
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The attached deposit must cover the receiver's storage")]
+    fn test_sender_deposit_too_small() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx.attached_deposit(cost - 1).build());
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+    }
+
+    #[test]
+    fn test_pool_pays_registration() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        enable_pool(ctx, &mut contract, 5);
+        let cost = contract.storage_balance_bounds().min.0;
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 900);
+        assert_eq!(
+            get_logs()[0],
+            format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"registration_sponsored","data":{{"account_id":"{}","sponsor_id":"{}","amount":"{}"}}}}"#,
+                accounts(2),
+                accounts(0),
+                cost
+            )
+        );
+        let info = contract.sponsor_pool_info();
+        assert_eq!(info.balance.0, ONE_NEAR - cost);
+        assert_eq!(info.remaining_in_period, 99);
+        assert_eq!(info.total_sponsored, U64(1));
+    }
+
+    #[test]
+    fn test_pool_sender_limit() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        enable_pool(ctx, &mut contract, 1);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        // The sender used up its sponsored registrations for the period.
+        contract.assert_deposit_and_register_receiver(&accounts(1), &accounts(4));
+        assert!(contract.storage_balance_of(accounts(4)).is_none());
+        assert_eq!(contract.sponsor_pool_info().total_sponsored, U64(1));
+    }
+
+    #[test]
+    fn test_pool_limit_resets_next_period() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        enable_pool(ctx, &mut contract, 1);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .block_timestamp(24 * 3_600 * NANOS_PER_SECOND)
+            .build());
+        contract.ft_transfer(accounts(4), U128(1_000), None);
+        assert_eq!(contract.ft_balance_of(accounts(4)).0, 900);
+        assert_eq!(contract.sponsor_pool_info().total_sponsored, U64(2));
+    }
+
+    #[test]
+    fn test_unregister_returns_deposit_to_pool() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        enable_pool(ctx, &mut contract, 5);
+        let cost = contract.storage_balance_bounds().min.0;
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(contract.sponsor_pool_info().balance.0, ONE_NEAR - cost);
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.sponsor_pool_info().balance.0, ONE_NEAR);
+        assert!(get_logs().contains(&format!(
+            r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"sponsored_deposit_returned","data":{{"account_id":"{}","sponsor_id":"{}","amount":"{}"}}}}"#,
+            accounts(2),
+            accounts(0),
+            cost
+        )));
+        assert!(contract.sponsor.sponsored.get(&accounts(2)).is_none());
+    }
+
+    #[test]
+    fn test_unregister_returns_what_the_pool_paid() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        enable_pool(ctx, &mut contract, 5);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        // The registration cost goes up after the account was sponsored.
+        contract.token.account_storage_usage += 100;
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .build());
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.sponsor_pool_info().balance.0, ONE_NEAR);
+    }
+
+    #[test]
+    fn test_unregister_returns_deposit_to_sender() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(cost)
+            .build());
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        assert_eq!(
+            contract.sponsor.sponsored.get(&accounts(2)),
+            Some(SponsoredRegistration {
+                sponsor_id: accounts(1),
+                amount: cost,
+            })
+        );
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(1)
+            .build());
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(get_logs().contains(&format!(
+            r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"sponsored_deposit_returned","data":{{"account_id":"{}","sponsor_id":"{}","amount":"{}"}}}}"#,
+            accounts(2),
+            accounts(1),
+            cost
+        )));
+        // Nothing goes back to the pool for a registration it didn't pay.
+        assert_eq!(contract.sponsor_pool_info().balance.0, 0);
+    }
+}
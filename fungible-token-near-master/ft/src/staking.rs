@@ -0,0 +1,626 @@
+//! Staking vault: holders stake MUTE in the token contract itself and earn rewards from a
+//! per-second emission and from a share of the transfer fees.
+//!
+//! Rewards are distributed with an accumulated-reward-per-share counter: every emission or fee
+//! share adds `amount / total_staked` to `reward_per_share`, and an account's pending rewards are
+//! `staked * (reward_per_share - snapshot)`. Emissions are paid from a reward pool that has to be
+//! funded with existing tokens, so staking never mints.
+//!
+//! Staked, unbonding and reward tokens are taken out of the account balances like locked tokens:
+//! they can't be transferred and don't take part in reflections.
+use crate::events::MuteEvent;
+use crate::launch::NANOS_PER_SECOND;
+use crate::math::{checked_mul_div, mul_div};
+use crate::*;
+use near_sdk::collections::LookupMap;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{Duration, IntoStorageKey, Timestamp};
+
+pub const REWARD_PRECISION: u128 = 1_000_000_000_000_000_000_000_000_000_000;
+/// Smallest position in whole tokens, so a dust stake can't blow up the reward per share.
+pub const MIN_STAKE_TOKENS: Balance = 1;
+pub const DEFAULT_UNBONDING_PERIOD: Duration = 7 * 24 * 3_600 * NANOS_PER_SECOND;
+/// Longest unbonding period the owner can set, 30 days.
+pub const MAX_UNBONDING_PERIOD_SECS: u64 = 30 * 24 * 3_600;
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct StakeAccount {
+    pub staked: Balance,
+    pub reward_snapshot: u128,
+    /// Rewards settled from the counter but not claimed yet.
+    pub rewards: Balance,
+    pub unbonding: Balance,
+    pub unbonding_until: Timestamp,
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct StakingVault {
+    pub accounts: LookupMap<AccountId, StakeAccount>,
+    pub total_staked: Balance,
+    pub total_unbonding: Balance,
+    pub reward_per_share: u128,
+    /// Emitted and fee rewards that were not claimed yet, including rounding dust.
+    pub unclaimed_rewards: Balance,
+    /// Funded rewards that were not emitted yet.
+    pub reward_pool: Balance,
+    pub reward_rate: Balance,
+    pub last_update: Timestamp,
+    /// Share of each transfer fee paid to stakers, in bps of the fee.
+    pub fee_bps: u16,
+    pub unbonding_period: Duration,
+}
+
+impl StakingVault {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self {
+            accounts: LookupMap::new(prefix),
+            total_staked: 0,
+            total_unbonding: 0,
+            reward_per_share: 0,
+            unclaimed_rewards: 0,
+            reward_pool: 0,
+            reward_rate: 0,
+            last_update: env::block_timestamp(),
+            fee_bps: 0,
+            unbonding_period: DEFAULT_UNBONDING_PERIOD,
+        }
+    }
+
+    /// Storage taken by one stake record, charged as part of the account registration.
+    pub fn measure_account_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.accounts
+            .insert(&tmp_account_id, &StakeAccount::default());
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.accounts.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    /// All tokens held by the vault.
+    pub fn held(&self) -> Balance {
+        self.total_staked + self.total_unbonding + self.unclaimed_rewards + self.reward_pool
+    }
+
+    pub fn staked_of(&self, account_id: &AccountId) -> Balance {
+        self.accounts
+            .get(account_id)
+            .map_or(0, |stake| stake.staked)
+    }
+
+    /// Emits the rewards accrued since the last update. Nothing is emitted while nobody stakes.
+    fn update(&mut self) {
+        let now = env::block_timestamp();
+        if now <= self.last_update {
+            return;
+        }
+        if self.total_staked > 0 {
+            let emitted = std::cmp::min(
+                mul_div(
+                    self.reward_rate,
+                    u128::from(now - self.last_update),
+                    u128::from(NANOS_PER_SECOND),
+                ),
+                self.reward_pool,
+            );
+            // Rewards too large for the total stake stay in the pool.
+            if self.add_rewards(emitted) {
+                self.reward_pool -= emitted;
+            }
+        }
+        self.last_update = now;
+    }
+
+    /// Spreads `amount` over the current stake. Returns false without changing anything if the
+    /// reward per share would overflow.
+    fn add_rewards(&mut self, amount: Balance) -> bool {
+        match self.reward_per_share_after(amount) {
+            Some(reward_per_share) => {
+                self.reward_per_share = reward_per_share;
+                self.unclaimed_rewards += amount;
+                true
+            }
+            None => {
+                MuteEvent::StakingRewardsSkipped {
+                    amount: amount.into(),
+                    total_staked: self.total_staked.into(),
+                }
+                .emit();
+                false
+            }
+        }
+    }
+
+    fn reward_per_share_after(&self, amount: Balance) -> Option<u128> {
+        checked_mul_div(amount, REWARD_PRECISION, self.total_staked)
+            .and_then(|increment| self.reward_per_share.checked_add(increment))
+    }
+
+    fn settle(&mut self, stake: &mut StakeAccount) {
+        self.update();
+        stake.rewards += mul_div(
+            stake.staked,
+            self.reward_per_share - stake.reward_snapshot,
+            REWARD_PRECISION,
+        );
+        stake.reward_snapshot = self.reward_per_share;
+    }
+
+    fn pending_rewards(&self, stake: &StakeAccount) -> Balance {
+        let mut reward_per_share = self.reward_per_share;
+        let now = env::block_timestamp();
+        if self.total_staked > 0 && now > self.last_update {
+            let emitted = std::cmp::min(
+                mul_div(
+                    self.reward_rate,
+                    u128::from(now - self.last_update),
+                    u128::from(NANOS_PER_SECOND),
+                ),
+                self.reward_pool,
+            );
+            reward_per_share = self
+                .reward_per_share_after(emitted)
+                .unwrap_or(reward_per_share);
+        }
+        stake.rewards
+            + mul_div(
+                stake.staked,
+                reward_per_share - stake.reward_snapshot,
+                REWARD_PRECISION,
+            )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakeView {
+    pub staked: U128,
+    pub pending_rewards: U128,
+    pub unbonding: U128,
+    pub unbonding_until: U64,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct StakingInfoView {
+    pub total_staked: U128,
+    pub total_unbonding: U128,
+    pub reward_pool: U128,
+    pub reward_rate: U128,
+    pub fee_bps: u16,
+    pub unbonding_period_secs: u64,
+    pub min_stake: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn staking_info(&self) -> StakingInfoView {
+        StakingInfoView {
+            total_staked: self.staking.total_staked.into(),
+            total_unbonding: self.staking.total_unbonding.into(),
+            reward_pool: self.staking.reward_pool.into(),
+            reward_rate: self.staking.reward_rate.into(),
+            fee_bps: self.staking.fee_bps,
+            unbonding_period_secs: self.staking.unbonding_period / NANOS_PER_SECOND,
+            min_stake: self.min_stake().into(),
+        }
+    }
+
+    pub fn stake_of(&self, account_id: AccountId) -> Option<StakeView> {
+        self.staking
+            .accounts
+            .get(&account_id)
+            .map(|stake| StakeView {
+                staked: stake.staked.into(),
+                pending_rewards: self.staking.pending_rewards(&stake).into(),
+                unbonding: stake.unbonding.into(),
+                unbonding_until: stake.unbonding_until.into(),
+            })
+    }
+
+    /// Balance, locked and staked tokens of the account. Unbonding tokens don't count.
+    pub fn ft_voting_power_of(&self, account_id: AccountId) -> U128 {
+        let locked = self
+            .locks
+            .locks
+            .get(&account_id)
+            .map_or(0, |lock| lock.amount);
+        (self.internal_balance_of(&account_id) + locked + self.staking.staked_of(&account_id))
+            .into()
+    }
+
+    #[payable]
+    pub fn stake(&mut self, amount: U128) -> StakeView {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        self.internal_debit(&account_id, amount.0);
+        let mut stake = self.staking.accounts.get(&account_id).unwrap_or_default();
+        let min_stake = self.min_stake();
+        require!(
+            stake.staked + amount.0 >= min_stake,
+            format!("The stake can't be less than {}", min_stake)
+        );
+        self.staking.settle(&mut stake);
+        stake.staked += amount.0;
+        self.staking.total_staked += amount.0;
+        self.staking.accounts.insert(&account_id, &stake);
+        MuteEvent::Staked {
+            account_id: &account_id,
+            amount,
+        }
+        .emit();
+        self.stake_of(account_id).unwrap()
+    }
+
+    /// Starts unbonding `amount`. Unbonding tokens earn nothing and can be withdrawn with
+    /// `withdraw_unstaked` after the unbonding period; unstaking more restarts the period.
+    #[payable]
+    pub fn unstake(&mut self, amount: U128) -> StakeView {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let mut stake = self.internal_unwrap_stake(&account_id);
+        require!(stake.staked >= amount.0, "Not enough staked tokens");
+        let remaining = stake.staked - amount.0;
+        let min_stake = self.min_stake();
+        require!(
+            remaining == 0 || remaining >= min_stake,
+            format!("The remaining stake can't be less than {}", min_stake)
+        );
+        self.staking.settle(&mut stake);
+        stake.staked -= amount.0;
+        stake.unbonding += amount.0;
+        stake.unbonding_until = env::block_timestamp() + self.staking.unbonding_period;
+        self.staking.total_staked -= amount.0;
+        self.staking.total_unbonding += amount.0;
+        self.staking.accounts.insert(&account_id, &stake);
+        MuteEvent::Unstaked {
+            account_id: &account_id,
+            amount,
+            unbonding_until: stake.unbonding_until.into(),
+        }
+        .emit();
+        self.stake_of(account_id).unwrap()
+    }
+
+    /// Returns unbonded tokens to the predecessor's balance.
+    #[payable]
+    pub fn withdraw_unstaked(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut stake = self.internal_unwrap_stake(&account_id);
+        require!(stake.unbonding > 0, "Nothing is unbonding");
+        require!(
+            stake.unbonding_until <= env::block_timestamp(),
+            format!("The tokens are unbonding until {}", stake.unbonding_until)
+        );
+        let amount = std::mem::take(&mut stake.unbonding);
+        self.staking.total_unbonding -= amount;
+        self.internal_save_stake(&account_id, stake);
+        self.internal_credit(&account_id, amount);
+        MuteEvent::UnstakedWithdrawn {
+            account_id: &account_id,
+            amount: amount.into(),
+        }
+        .emit();
+        amount.into()
+    }
+
+    /// Pays the predecessor's staking rewards out to its balance.
+    #[payable]
+    pub fn claim_rewards(&mut self) -> U128 {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut stake = self.internal_unwrap_stake(&account_id);
+        self.staking.settle(&mut stake);
+        let amount = std::mem::take(&mut stake.rewards);
+        require!(amount > 0, "No rewards to claim");
+        self.staking.unclaimed_rewards -= amount;
+        self.internal_save_stake(&account_id, stake);
+        self.internal_credit(&account_id, amount);
+        MuteEvent::StakingRewardsClaimed {
+            account_id: &account_id,
+            amount: amount.into(),
+        }
+        .emit();
+        amount.into()
+    }
+
+    /// Moves `amount` from the predecessor's balance into the reward pool.
+    #[payable]
+    pub fn fund_staking_rewards(&mut self, amount: U128) {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        self.staking.update();
+        self.internal_debit(&account_id, amount.0);
+        self.staking.reward_pool += amount.0;
+        MuteEvent::StakingRewardsFunded {
+            account_id: &account_id,
+            amount,
+        }
+        .emit();
+    }
+
+    /// Sets the emission in tokens per second, paid from the reward pool while it lasts.
+    #[payable]
+    pub fn set_staking_reward_rate(&mut self, reward_rate: U128) {
+        self.assert_owner();
+        self.staking.update();
+        self.staking.reward_rate = reward_rate.0;
+        self.emit_staking_config();
+    }
+
+    #[payable]
+    pub fn set_staking_fee_bps(&mut self, fee_bps: u16) {
+        self.assert_owner();
+        require!(
+            u32::from(fee_bps) + u32::from(self.fee_burn_bps) + u32::from(self.reflection.bps)
+                <= u32::from(fees::FEE_BPS_DENOMINATOR),
+            "Burn, reflection and staking shares can't exceed 10000 bps"
+        );
+        self.staking.fee_bps = fee_bps;
+        self.emit_staking_config();
+    }
+
+    #[payable]
+    pub fn set_unbonding_period(&mut self, unbonding_period_secs: u64) {
+        self.assert_owner();
+        require!(
+            unbonding_period_secs <= MAX_UNBONDING_PERIOD_SECS,
+            format!(
+                "The unbonding period can't exceed {} seconds",
+                MAX_UNBONDING_PERIOD_SECS
+            )
+        );
+        self.staking.unbonding_period = unbonding_period_secs * NANOS_PER_SECOND;
+        self.emit_staking_config();
+    }
+}
+
+impl Contract {
+    /// `MIN_STAKE_TOKENS` in token units, by the metadata decimals.
+    fn min_stake(&self) -> Balance {
+        MIN_STAKE_TOKENS * self.token_unit()
+    }
+
+    fn internal_unwrap_stake(&self, account_id: &AccountId) -> StakeAccount {
+        self.staking
+            .accounts
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str("The account has no stake"))
+    }
+
+    /// Drops the record once nothing is left in it, so the account can unregister.
+    fn internal_save_stake(&mut self, account_id: &AccountId, stake: StakeAccount) {
+        if stake.staked == 0 && stake.unbonding == 0 && stake.rewards == 0 {
+            self.staking.accounts.remove(account_id);
+        } else {
+            self.staking.accounts.insert(account_id, &stake);
+        }
+    }
+
+    /// Takes `amount` from the sender and adds it to the stakers' rewards. Returns false without
+    /// moving anything while nobody stakes or if the amount is too large for the total stake,
+    /// the fee then goes to the fee bucket instead.
+    pub(crate) fn reward_stakers(&mut self, sender_id: &AccountId, amount: Balance) -> bool {
+        if self.staking.total_staked == 0 {
+            return false;
+        }
+        self.staking.update();
+        if !self.staking.add_rewards(amount) {
+            return false;
+        }
+        self.internal_debit(sender_id, amount);
+        true
+    }
+
+    fn emit_staking_config(&self) {
+        MuteEvent::StakingConfigUpdated {
+            reward_rate: self.staking.reward_rate.into(),
+            fee_bps: self.staking.fee_bps,
+            unbonding_period_secs: self.staking.unbonding_period / NANOS_PER_SECOND,
+        }
+        .emit();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    /// Token units in one whole token at the default decimals.
+    const TOKEN: Balance = 10u128.pow(DEFAULT_DECIMALS as u32);
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        register_user(ctx, &mut contract, 4);
+        at(ctx, 1, 0);
+        contract.ft_transfer(accounts(2), U128(100 * TOKEN), None);
+        contract.ft_transfer(accounts(4), U128(100 * TOKEN), None);
+        contract.fund_staking_rewards(U128(1_000_000));
+        contract.set_staking_reward_rate(U128(100));
+        contract
+    }
+
+    fn at(ctx: &mut VMContextBuilder, id: usize, secs: u64) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(id))
+            .block_timestamp(secs * NANOS_PER_SECOND)
+            .build());
+    }
+
+    fn pending(contract: &Contract, id: usize) -> Balance {
+        contract.stake_of(accounts(id)).unwrap().pending_rewards.0
+    }
+
+    #[test]
+    fn test_emissions_are_shared_by_stake() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, 10);
+        contract.stake(U128(20 * TOKEN));
+        at(ctx, 4, 20);
+        contract.stake(U128(5 * TOKEN));
+        assert_eq!(pending(&contract, 2), 1_000);
+
+        at(ctx, 4, 60);
+        assert_eq!(pending(&contract, 2), 4_200);
+        assert_eq!(pending(&contract, 4), 800);
+
+        at(ctx, 2, 60);
+        assert_eq!(contract.claim_rewards(), U128(4_200));
+        assert_eq!(
+            contract.ft_balance_of(accounts(2)).0,
+            90 * TOKEN - 20 * TOKEN + 4_200
+        );
+        assert_eq!(contract.staking_info().reward_pool, U128(1_000_000 - 5_000));
+        assert_eq!(
+            contract.ft_voting_power_of(accounts(2)).0,
+            90 * TOKEN + 4_200
+        );
+    }
+
+    #[test]
+    fn test_emissions_stop_when_pool_is_empty() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_staking_reward_rate(U128(1_000_000));
+        at(ctx, 2, 0);
+        contract.stake(U128(20 * TOKEN));
+        at(ctx, 2, 100);
+        assert_eq!(pending(&contract, 2), 1_000_000);
+        assert_eq!(contract.claim_rewards(), U128(1_000_000));
+        assert_eq!(contract.staking_info().reward_pool, U128(0));
+    }
+
+    #[test]
+    fn test_unstake_with_unbonding() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_unbonding_period(3_600);
+        at(ctx, 2, 0);
+        contract.stake(U128(20 * TOKEN));
+        at(ctx, 2, 10);
+        let stake = contract.unstake(U128(20 * TOKEN));
+        assert_eq!(stake.unbonding, U128(20 * TOKEN));
+        assert_eq!(stake.pending_rewards, U128(1_000));
+        // Unbonding tokens earn nothing.
+        at(ctx, 2, 3_610);
+        assert_eq!(pending(&contract, 2), 1_000);
+        assert_eq!(contract.withdraw_unstaked(), U128(20 * TOKEN));
+        assert_eq!(contract.claim_rewards(), U128(1_000));
+        assert_eq!(contract.stake_of(accounts(2)), None);
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, 90 * TOKEN + 1_000);
+    }
+
+    #[test]
+    fn test_stakers_get_fee_share() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_staking_reward_rate(U128(0));
+        contract.set_staking_fee_bps(5_000);
+        at(ctx, 2, 0);
+        contract.stake(U128(20 * TOKEN));
+
+        at(ctx, 1, 0);
+        contract.ft_transfer(accounts(4), U128(10_000), None);
+        assert_eq!(pending(&contract, 2), 500);
+        assert_eq!(contract.ft_accrued_fees().0, 20 * TOKEN + 500);
+        // Staked tokens also count for fee discounts.
+        contract.set_fee_discount_tiers(vec![fees::FeeDiscountTier {
+            min_locked: U128(20 * TOKEN),
+            discount_bps: 10_000,
+        }]);
+        assert_eq!(
+            contract.calculate_fee(&accounts(2), &accounts(4), U128(10_000)),
+            (U128(10_000), Some(U128(0)))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "The tokens are unbonding until")]
+    fn test_withdraw_before_unbonding_ends() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, 0);
+        contract.stake(U128(20 * TOKEN));
+        contract.unstake(U128(10 * TOKEN));
+        contract.withdraw_unstaked();
+    }
+
+    #[test]
+    fn test_min_stake_is_one_whole_token() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        assert_eq!(contract.staking_info().min_stake, U128(TOKEN));
+        contract.set_staking_reward_rate(U128(0));
+        contract.set_staking_fee_bps(3_000);
+        at(ctx, 2, 0);
+        contract.stake(U128(TOKEN));
+
+        // A 30% staking share of the 10% fee on a million tokens fits the minimum stake.
+        at(ctx, 1, 0);
+        contract.ft_transfer(accounts(4), U128(1_000_000 * TOKEN), None);
+        assert_eq!(pending(&contract, 2), 30_000 * TOKEN);
+    }
+
+    #[test]
+    fn test_oversized_fee_share_goes_to_fee_bucket() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        contract.set_staking_reward_rate(U128(0));
+        contract.set_staking_fee_bps(10_000);
+        at(ctx, 2, 0);
+        contract.stake(U128(TOKEN));
+
+        // The fee on 5 billion tokens doesn't fit the reward per share of a single token.
+        at(ctx, 1, 0);
+        let accrued = contract.ft_accrued_fees().0;
+        contract.ft_transfer(accounts(4), U128(5_000_000_000 * TOKEN), None);
+        assert_eq!(contract.ft_accrued_fees().0, accrued + 500_000_000 * TOKEN);
+        assert_eq!(pending(&contract, 2), 0);
+        assert!(get_logs().contains(&format!(
+            r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"staking_rewards_skipped","data":{{"amount":"{}","total_staked":"{}"}}}}"#,
+            500_000_000 * TOKEN,
+            TOKEN
+        )));
+
+        // Small fees still reach the stakers.
+        contract.ft_transfer(accounts(4), U128(10_000), None);
+        assert_eq!(pending(&contract, 2), 1_000);
+    }
+
+    #[test]
+    fn test_oversized_emission_stays_in_pool() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let pool = 1_000_000_000 * TOKEN;
+        contract.fund_staking_rewards(U128(pool));
+        contract.set_staking_reward_rate(U128(pool));
+        at(ctx, 2, 0);
+        contract.stake(U128(TOKEN));
+        at(ctx, 2, 10);
+        assert_eq!(pending(&contract, 2), 0);
+        contract.unstake(U128(TOKEN));
+        assert_eq!(contract.staking_info().reward_pool, U128(1_000_000 + pool));
+    }
+
+    #[test]
+    #[should_panic(expected = "The stake can't be less than 1000000000000000000")]
+    fn test_dust_stake_rejected() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        at(ctx, 2, 0);
+        contract.stake(U128(TOKEN - 1));
+    }
+}
@@ -0,0 +1,210 @@
+//! Persistent fee accounting: totals, amounts paid to each fee receiver and per-epoch buckets.
+use crate::*;
+use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{EpochHeight, IntoStorageKey};
+
+/// Longest epoch range `ft_fee_stats_by_epoch` walks through at once.
+pub const MAX_STATS_EPOCHS: u64 = 100;
+
+#[derive(BorshDeserialize, BorshSerialize, Default, Clone, Copy)]
+pub struct FeeTotals {
+    pub collected: Balance,
+    pub burned: Balance,
+    pub reflected: Balance,
+    pub taxed_transfers: u64,
+}
+
+impl FeeTotals {
+    fn add(&mut self, fee: Balance, burned: Balance, reflected: Balance) {
+        self.collected += fee;
+        self.burned += burned;
+        self.reflected += reflected;
+        self.taxed_transfers += 1;
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct FeeStats {
+    pub totals: FeeTotals,
+    /// Fees paid out to each receiver by `claim_fees`.
+    pub paid: UnorderedMap<AccountId, Balance>,
+    pub epochs: LookupMap<EpochHeight, FeeTotals>,
+}
+
+impl FeeStats {
+    pub fn new<S, T>(paid_prefix: S, epochs_prefix: T) -> Self
+    where
+        S: IntoStorageKey,
+        T: IntoStorageKey,
+    {
+        Self {
+            totals: FeeTotals::default(),
+            paid: UnorderedMap::new(paid_prefix),
+            epochs: LookupMap::new(epochs_prefix),
+        }
+    }
+
+    /// Counts a taxed transfer in the totals and in the current epoch's bucket.
+    pub fn record_fee(&mut self, fee: Balance, burned: Balance, reflected: Balance) {
+        self.totals.add(fee, burned, reflected);
+        let epoch = env::epoch_height();
+        let mut bucket = self.epochs.get(&epoch).unwrap_or_default();
+        bucket.add(fee, burned, reflected);
+        self.epochs.insert(&epoch, &bucket);
+    }
+
+    pub fn record_payout(&mut self, account_id: &AccountId, amount: Balance) {
+        let paid = self.paid.get(account_id).unwrap_or(0);
+        self.paid.insert(account_id, &(paid + amount));
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeTotalsView {
+    pub collected: U128,
+    pub burned: U128,
+    pub reflected: U128,
+    pub taxed_transfers: U64,
+}
+
+impl From<FeeTotals> for FeeTotalsView {
+    fn from(totals: FeeTotals) -> Self {
+        Self {
+            collected: totals.collected.into(),
+            burned: totals.burned.into(),
+            reflected: totals.reflected.into(),
+            taxed_transfers: totals.taxed_transfers.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FeeStatsView {
+    #[serde(flatten)]
+    pub totals: FeeTotalsView,
+    pub accrued: U128,
+    pub claimed: U128,
+    /// Fees paid out to each receiver so far.
+    pub receivers: Vec<(AccountId, U128)>,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn ft_fee_stats(&self) -> FeeStatsView {
+        FeeStatsView {
+            totals: self.fee_stats.totals.into(),
+            accrued: self.accrued_fees.into(),
+            claimed: self.claimed_fees.into(),
+            receivers: self
+                .fee_stats
+                .paid
+                .iter()
+                .map(|(account_id, amount)| (account_id, amount.into()))
+                .collect(),
+        }
+    }
+
+    /// Fee totals of the epochs in `from..=to` that had taxed transfers.
+    pub fn ft_fee_stats_by_epoch(&self, from: U64, to: U64) -> Vec<(U64, FeeTotalsView)> {
+        require!(from.0 <= to.0, "from can't be greater than to");
+        require!(
+            to.0 - from.0 < MAX_STATS_EPOCHS,
+            format!("At most {} epochs can be queried at once", MAX_STATS_EPOCHS)
+        );
+        (from.0..=to.0)
+            .filter_map(|epoch| {
+                self.fee_stats
+                    .epochs
+                    .get(&epoch)
+                    .map(|totals| (U64(epoch), totals.into()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user, resolve_with};
+
+    fn transfer_in_epoch(
+        ctx: &mut VMContextBuilder,
+        contract: &mut Contract,
+        epoch: EpochHeight,
+        amount: Balance,
+    ) {
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .epoch_height(epoch)
+            .build());
+        contract.ft_transfer(accounts(2), U128(amount), None);
+    }
+
+    #[test]
+    fn test_fee_stats() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        transfer_in_epoch(ctx, &mut contract, 1, 1_000);
+        contract.set_fee_burn_bps(1_000);
+        transfer_in_epoch(ctx, &mut contract, 1, 2_000);
+        transfer_in_epoch(ctx, &mut contract, 3, 10_000);
+
+        testing_env!(ctx.predecessor_account_id(accounts(3)).build());
+        contract.claim_fees();
+        let stats = contract.ft_fee_stats();
+        assert_eq!(
+            stats.totals,
+            FeeTotalsView {
+                collected: U128(1_300),
+                burned: U128(120),
+                reflected: U128(0),
+                taxed_transfers: U64(3),
+            }
+        );
+        assert_eq!(stats.claimed, U128(1_180));
+        assert_eq!(stats.receivers, vec![(accounts(3), U128(1_180))]);
+
+        let epochs = contract.ft_fee_stats_by_epoch(U64(0), U64(5));
+        assert_eq!(epochs.len(), 2);
+        assert_eq!(epochs[0].0, U64(1));
+        assert_eq!(epochs[0].1.collected, U128(300));
+        assert_eq!(epochs[0].1.taxed_transfers, U64(2));
+        assert_eq!(epochs[1].0, U64(3));
+        assert_eq!(epochs[1].1.burned, U128(100));
+    }
+
+    #[test]
+    fn test_fee_stats_count_settled_transfer_calls_only() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.ft_transfer_call(accounts(2), U128(1_000), None, "".into());
+        resolve_with(ctx, PromiseResult::Failed);
+        contract.ft_resolve_transfer_with_fee(accounts(1), accounts(2), U128(900), U128(100), None);
+        assert_eq!(contract.ft_fee_stats().totals.taxed_transfers, U64(0));
+
+        testing_env!(ctx
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.ft_transfer_call(accounts(2), U128(1_000), None, "".into());
+        resolve_with(ctx, PromiseResult::Successful(b"\"0\"".to_vec()));
+        contract.ft_resolve_transfer_with_fee(accounts(1), accounts(2), U128(900), U128(100), None);
+        assert_eq!(contract.ft_fee_stats().totals.collected, U128(100));
+    }
+}
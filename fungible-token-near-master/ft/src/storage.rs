@@ -0,0 +1,247 @@
+//! NEP-145 storage management. Mirrors `impl_fungible_token_storage!`, but keeps the contract's
+//! own per-account indexes in sync with registration.
+//!
+//! The balance of an account closed with `storage_unregister(force=true)` is burned, or sent to
+//! the treasury (the owner) or the fee receiver, as the owner chooses.
+use crate::events::MuteEvent;
+use crate::*;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::Promise;
+
+#[derive(
+    BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, Debug, PartialEq,
+)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum ClosedBalancePolicy {
+    Burn,
+    Treasury,
+    FeeReceiver,
+}
+
+#[near_bindgen]
+impl StorageManagement for Contract {
+    #[payable]
+    fn storage_deposit(
+        &mut self,
+        account_id: Option<AccountId>,
+        registration_only: Option<bool>,
+    ) -> StorageBalance {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let storage_balance = self
+            .token
+            .storage_deposit(Some(account_id.clone()), registration_only);
+        self.holders.insert(&account_id);
+        storage_balance
+    }
+
+    #[payable]
+    fn storage_withdraw(&mut self, amount: Option<U128>) -> StorageBalance {
+        self.token.storage_withdraw(amount)
+    }
+
+    #[payable]
+    fn storage_unregister(&mut self, force: Option<bool>) -> bool {
+        let account_id = env::predecessor_account_id();
+        MuteError::FeeReceiverCantUnregister.check(!self.is_fee_receiver(&account_id));
+        MuteError::AccountHasLockedTokens.check(self.locks.locks.get(&account_id).is_none());
+        MuteError::AccountHasStake.check(self.staking.accounts.get(&account_id).is_none());
+        MuteError::AccountHasAuctionCommitment
+            .check(self.auction.commitments.get(&account_id).is_none());
+        self.settle_reflection(&account_id);
+        if let Some(account_id) = self.internal_storage_unregister(force) {
+            let history_deposit = self.history.clear(&account_id);
+            if history_deposit > 0 {
+                Promise::new(account_id.clone()).transfer(history_deposit);
+            }
+            self.on_account_closed(account_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn storage_balance_bounds(&self) -> StorageBalanceBounds {
+        self.token.storage_balance_bounds()
+    }
+
+    /// Includes the deposit reserved for the account's transfer history, if enabled.
+    fn storage_balance_of(&self, account_id: AccountId) -> Option<StorageBalance> {
+        let history_deposit = self.history.deposit_of(&account_id);
+        self.token
+            .storage_balance_of(account_id)
+            .map(|storage_balance| StorageBalance {
+                total: U128(storage_balance.total.0 + history_deposit),
+                available: storage_balance.available,
+            })
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn closed_balance_policy(&self) -> ClosedBalancePolicy {
+        self.closed_balance_policy
+    }
+
+    /// Chooses what happens to the balance of accounts closed with `force`.
+    #[payable]
+    pub fn set_closed_balance_policy(&mut self, policy: ClosedBalancePolicy) {
+        self.assert_owner();
+        self.closed_balance_policy = policy;
+        MuteEvent::ClosedBalancePolicyUpdated { policy }.emit();
+    }
+}
+
+impl Contract {
+    /// Like `FungibleToken::internal_storage_unregister`, but disposes of the balance by the
+    /// closed balance policy before removing the account. The balance is burned if the chosen
+    /// receiver is the closed account itself or isn't registered. The storage deposit of a
+    /// sponsored account goes back to its sponsor.
+    fn internal_storage_unregister(&mut self, force: Option<bool>) -> Option<AccountId> {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let balance = match self.token.accounts.get(&account_id) {
+            Some(balance) => balance,
+            None => {
+                log!("The account {} is not registered", &account_id);
+                return None;
+            }
+        };
+        MuteError::PositiveBalanceWithoutForce.check(balance == 0 || force.unwrap_or(false));
+        if balance > 0 {
+            let receiver_id = match self.closed_balance_policy {
+                ClosedBalancePolicy::Burn => None,
+                ClosedBalancePolicy::Treasury => Some(self.owner_id.clone()),
+                ClosedBalancePolicy::FeeReceiver => Some(self.fee_receiver.clone()),
+            }
+            .filter(|receiver_id| {
+                receiver_id != &account_id && self.token.accounts.contains_key(receiver_id)
+            });
+            match &receiver_id {
+                Some(receiver_id) => self.internal_transfer(
+                    &account_id,
+                    receiver_id,
+                    balance,
+                    Some("account_closed".to_string()),
+                ),
+                None => self.internal_burn(&account_id, balance, "account_closed"),
+            }
+            MuteEvent::ClosedBalanceDisposed {
+                account_id: &account_id,
+                amount: balance.into(),
+                receiver_id: receiver_id.as_ref(),
+            }
+            .emit();
+        }
+        self.token.accounts.remove(&account_id);
+        let refund =
+            self.return_sponsored_deposit(&account_id, self.storage_balance_bounds().min.0);
+        Promise::new(account_id.clone()).transfer(refund + 1);
+        Some(account_id)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    fn setup(ctx: &mut VMContextBuilder, policy: ClosedBalancePolicy) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, &mut contract, 2);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.set_closed_balance_policy(policy);
+        contract.ft_transfer(accounts(2), U128(1_000), None);
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract
+    }
+
+    #[test]
+    fn test_force_close_burns_by_default() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::Burn);
+        let supply = contract.ft_total_supply().0;
+        assert!(contract.storage_unregister(Some(true)));
+        assert!(contract.storage_balance_of(accounts(2)).is_none());
+        assert_eq!(contract.ft_total_supply().0, supply - 900);
+        assert_eq!(contract.ft_total_burned().0, 900);
+        assert_eq!(
+            get_logs().last().unwrap(),
+            &format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"closed_balance_disposed","data":{{"account_id":"{}","amount":"900","receiver_id":null}}}}"#,
+                accounts(2)
+            )
+        );
+    }
+
+    #[test]
+    fn test_force_close_sends_to_treasury() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::Treasury);
+        let supply = contract.ft_total_supply().0;
+        let owner_balance = contract.ft_balance_of(accounts(1)).0;
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(contract.ft_total_supply().0, supply);
+        assert_eq!(contract.ft_total_burned().0, 0);
+        assert_eq!(contract.ft_balance_of(accounts(1)).0, owner_balance + 900);
+    }
+
+    #[test]
+    fn test_force_close_sends_to_fee_receiver() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::FeeReceiver);
+        let fee_receiver_balance = contract.ft_balance_of(accounts(3)).0;
+        assert!(contract.storage_unregister(Some(true)));
+        assert_eq!(
+            contract.ft_balance_of(accounts(3)).0,
+            fee_receiver_balance + 900
+        );
+        assert_eq!(
+            get_logs().last().unwrap(),
+            &format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"closed_balance_disposed","data":{{"account_id":"{}","amount":"900","receiver_id":"{}"}}}}"#,
+                accounts(2),
+                accounts(3)
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "E019: Can't unregister the account with the positive balance without force"
+    )]
+    fn test_close_with_balance_requires_force() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::Burn);
+        contract.storage_unregister(None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E015: Fee receivers can't unregister")]
+    fn test_fee_receiver_cant_unregister() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::Burn);
+        testing_env!(ctx.predecessor_account_id(accounts(3)).build());
+        contract.storage_unregister(Some(true));
+    }
+
+    #[test]
+    #[should_panic(expected = "E016: The account has locked tokens")]
+    fn test_close_with_locked_tokens() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx, ClosedBalancePolicy::Burn);
+        contract.lock(U128(100), 60);
+        contract.storage_unregister(Some(true));
+    }
+}
@@ -0,0 +1,190 @@
+//! Circulating supply, i.e. the total supply minus balances that are not on the market.
+use crate::events::MuteEvent;
+use crate::*;
+use near_contract_standards::fungible_token::events::FtBurn;
+
+/// Keeps `ft_circulating_supply` within view gas limits.
+pub const MAX_NON_CIRCULATING_ACCOUNTS: u64 = 50;
+
+#[near_bindgen]
+impl Contract {
+    /// Total supply minus the balances of the non-circulating accounts and of the contract account
+    /// itself, and minus the tokens held outside of balances: locked, staked and auctioned tokens,
+    /// undistributed reflections and unclaimed fees.
+    pub fn ft_circulating_supply(&self) -> U128 {
+        let contract_id = env::current_account_id();
+        let excluded: Balance = self
+            .non_circulating
+            .iter()
+            .filter(|account_id| account_id != &contract_id)
+            .chain(std::iter::once(contract_id.clone()))
+            .map(|account_id| self.ft_balance_of(account_id).0)
+            .sum();
+        U128(
+            self.ft_total_supply()
+                .0
+                .saturating_sub(excluded)
+                .saturating_sub(self.held_outside_balances()),
+        )
+    }
+
+    /// Tokens burned over the lifetime of the contract.
+    pub fn ft_total_burned(&self) -> U128 {
+        self.total_burned.into()
+    }
+
+    pub fn non_circulating_accounts(&self) -> Vec<AccountId> {
+        self.non_circulating.to_vec()
+    }
+
+    #[payable]
+    pub fn add_non_circulating_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        require!(
+            self.non_circulating.len() < MAX_NON_CIRCULATING_ACCOUNTS,
+            "Too many non-circulating accounts"
+        );
+        if self.non_circulating.insert(&account_id) {
+            MuteEvent::NonCirculatingAccountUpdated {
+                account_id: &account_id,
+                non_circulating: true,
+            }
+            .emit();
+        }
+    }
+
+    #[payable]
+    pub fn remove_non_circulating_account(&mut self, account_id: AccountId) {
+        self.assert_owner();
+        if self.non_circulating.remove(&account_id) {
+            MuteEvent::NonCirculatingAccountUpdated {
+                account_id: &account_id,
+                non_circulating: false,
+            }
+            .emit();
+        }
+    }
+}
+
+impl Contract {
+    /// Tokens the contract keeps track of outside of account balances.
+    pub(crate) fn held_outside_balances(&self) -> Balance {
+        self.reflection.pool
+            + self.accrued_fees
+            + self.locks.total_locked
+            + self.staking.held()
+            + self.auction.held
+    }
+
+    /// Units in a whole token, by the metadata decimals.
+    pub(crate) fn token_unit(&self) -> Balance {
+        10u128.pow(self.metadata.get().unwrap().decimals as u32)
+    }
+
+    pub(crate) fn internal_burn(&mut self, account_id: &AccountId, amount: Balance, memo: &str) {
+        self.settle_reflection(account_id);
+        MuteError::InsufficientBalance
+            .check(self.internal_registered_balance(account_id) >= amount);
+        self.token.internal_withdraw(account_id, amount);
+        self.total_burned += amount;
+        FtBurn {
+            owner_id: account_id,
+            amount: &U128(amount),
+            memo: Some(memo),
+        }
+        .emit();
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::{get_context, register_user};
+
+    #[test]
+    fn test_circulating_supply() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = &mut Contract::new_default_config(accounts(1), accounts(3));
+        register_user(ctx, contract, 0);
+        register_user(ctx, contract, 2);
+
+        testing_env!(ctx
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract
+            .token
+            .internal_transfer(&accounts(1), &accounts(2), 1_000, None);
+        contract
+            .token
+            .internal_transfer(&accounts(1), &accounts(0), 500, None);
+        assert_eq!(
+            contract.ft_circulating_supply().0,
+            DEFAULT_TOTAL_SUPPLY - 500
+        );
+
+        contract.add_non_circulating_account(accounts(1));
+        contract.add_non_circulating_account(accounts(0));
+        assert_eq!(contract.ft_circulating_supply().0, 1_000);
+        assert_eq!(
+            contract.non_circulating_accounts(),
+            vec![accounts(1), accounts(0)]
+        );
+
+        contract.remove_non_circulating_account(accounts(1));
+        assert_eq!(
+            contract.ft_circulating_supply().0,
+            DEFAULT_TOTAL_SUPPLY - 500
+        );
+        assert_eq!(
+            get_logs().last().unwrap(),
+            &format!(
+                r#"EVENT_JSON:{{"standard":"mute","version":"1.0.0","event":"non_circulating_account_updated","data":{{"account_id":"{}","non_circulating":false}}}}"#,
+                accounts(1)
+            )
+        );
+
+        // Adding an account twice doesn't emit another event.
+        let logs = get_logs().len();
+        contract.add_non_circulating_account(accounts(0));
+        assert_eq!(get_logs().len(), logs);
+    }
+
+    #[test]
+    fn test_circulating_supply_excludes_locked_and_staked() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        let stake = 2 * contract.token_unit();
+        contract.lock(U128(1_000), 3_600);
+        contract.stake(U128(stake));
+        assert_eq!(
+            contract.ft_circulating_supply().0,
+            DEFAULT_TOTAL_SUPPLY - 1_000 - stake
+        );
+
+        contract.add_non_circulating_account(accounts(1));
+        assert_eq!(contract.ft_circulating_supply().0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E014: Only the owner can call this method")]
+    fn test_only_owner_manages_non_circulating() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.add_non_circulating_account(accounts(2));
+    }
+}
@@ -0,0 +1,67 @@
+//! Batched read-only queries.
+use crate::*;
+
+/// Upper bound on the number of accounts accepted by a single batched view.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+#[near_bindgen]
+impl Contract {
+    pub fn ft_balances_of(&self, account_ids: Vec<AccountId>) -> Vec<U128> {
+        assert_batch_size(account_ids.len());
+        account_ids
+            .into_iter()
+            .map(|account_id| self.ft_balance_of(account_id))
+            .collect()
+    }
+
+    pub fn storage_balances_of(&self, account_ids: Vec<AccountId>) -> Vec<Option<StorageBalance>> {
+        assert_batch_size(account_ids.len());
+        account_ids
+            .into_iter()
+            .map(|account_id| self.storage_balance_of(account_id))
+            .collect()
+    }
+}
+
+fn assert_batch_size(len: usize) {
+    require!(
+        len <= MAX_BATCH_SIZE,
+        format!("At most {} accounts can be queried at once", MAX_BATCH_SIZE)
+    );
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::accounts;
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::get_context;
+
+    #[test]
+    fn test_batched_views() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx.is_view(true).build());
+
+        let balances = contract.ft_balances_of(vec![accounts(1), accounts(2), accounts(3)]);
+        assert_eq!(balances, vec![U128(DEFAULT_TOTAL_SUPPLY), U128(0), U128(0)]);
+
+        let storage = contract.storage_balances_of(vec![accounts(1), accounts(2)]);
+        assert_eq!(
+            storage[0].as_ref().map(|s| s.total),
+            Some(contract.storage_balance_bounds().min)
+        );
+        assert!(storage[1].is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "At most 100 accounts can be queried at once")]
+    fn test_batch_size_cap() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let contract = Contract::new_default_config(accounts(1), accounts(3));
+        contract.ft_balances_of(vec![accounts(2); MAX_BATCH_SIZE + 1]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Wrapped NEAR mode: `near_deposit` mints tokens for the attached NEAR, at one whole token per
+//! NEAR, and `near_withdraw` burns them to send the NEAR back.
+//!
+//! The NEAR backing minted tokens is tracked in `wrapped_near`, apart from storage deposits, so
+//! the contract always holds enough to pay out every withdrawal on top of its storage. Each
+//! account can only withdraw up to the tokens it wrapped itself, kept in `wrapped_balances`.
+use crate::events::MuteEvent;
+use crate::math::mul_div;
+use crate::*;
+use near_contract_standards::fungible_token::events::FtMint;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{Promise, ONE_NEAR};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WrapInfo {
+    pub enabled: bool,
+    /// NEAR held against minted tokens, in yoctoNEAR.
+    pub wrapped_near: U128,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn wrap_info(&self) -> WrapInfo {
+        WrapInfo {
+            enabled: self.wrap_enabled,
+            wrapped_near: self.wrapped_near.into(),
+        }
+    }
+
+    /// Tokens the account minted with `near_deposit` and can still withdraw.
+    pub fn wrapped_balance_of(&self, account_id: AccountId) -> U128 {
+        self.wrapped_balances.get(&account_id).unwrap_or(0).into()
+    }
+
+    /// Withdrawals stay possible while the mode is disabled.
+    #[payable]
+    pub fn set_wrap_enabled(&mut self, enabled: bool) {
+        self.assert_owner();
+        self.wrap_enabled = enabled;
+        MuteEvent::WrapModeUpdated { enabled }.emit();
+    }
+
+    /// Mints tokens for the attached NEAR to the predecessor and returns the amount minted.
+    /// Unregistered accounts are registered first, paying the storage out of the deposit. NEAR
+    /// below the smallest token unit is refunded.
+    #[payable]
+    pub fn near_deposit(&mut self) -> U128 {
+        require!(self.wrap_enabled, "Wrapping NEAR is disabled");
+        let account_id = env::predecessor_account_id();
+        let mut deposit = env::attached_deposit();
+        if !self.token.accounts.contains_key(&account_id) {
+            let storage_cost = self.storage_balance_bounds().min.0;
+            require!(
+                deposit > storage_cost,
+                format!(
+                    "The deposit must exceed {} to cover the storage of a new account",
+                    storage_cost
+                )
+            );
+            self.internal_register_account(&account_id);
+            deposit -= storage_cost;
+        }
+        let amount = self.near_to_tokens(deposit);
+        require!(
+            amount > 0,
+            "The deposit is less than the smallest token unit"
+        );
+        let backing = self.tokens_to_near(amount);
+
+        self.settle_reflection(&account_id);
+        self.token.internal_deposit(&account_id, amount);
+        self.wrapped_near += backing;
+        let wrapped = self.wrapped_balances.get(&account_id).unwrap_or(0);
+        self.wrapped_balances
+            .insert(&account_id, &(wrapped + amount));
+        self.assert_transfer_limits(None, &account_id, amount);
+        FtMint {
+            owner_id: &account_id,
+            amount: &U128(amount),
+            memo: Some("near_deposit"),
+        }
+        .emit();
+        if deposit > backing {
+            Promise::new(account_id).transfer(deposit - backing);
+        }
+        amount.into()
+    }
+
+    /// Burns `amount` of the predecessor's tokens and sends the NEAR backing them, up to the
+    /// tokens the predecessor wrapped.
+    #[payable]
+    pub fn near_withdraw(&mut self, amount: U128) -> Promise {
+        assert_one_yocto();
+        require!(amount.0 > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let wrapped = self.wrapped_balances.get(&account_id).unwrap_or(0);
+        require!(
+            amount.0 <= wrapped,
+            "The amount exceeds the wrapped balance of the account"
+        );
+        let payout = self.tokens_to_near(amount.0);
+        self.internal_burn(&account_id, amount.0, "near_withdraw");
+        self.wrapped_near -= payout;
+        if wrapped > amount.0 {
+            self.wrapped_balances
+                .insert(&account_id, &(wrapped - amount.0));
+        } else {
+            self.wrapped_balances.remove(&account_id);
+        }
+        Promise::new(account_id).transfer(payout)
+    }
+}
+
+impl Contract {
+    /// Storage taken by the wrapped balance of one account, charged as part of the registration.
+    pub(crate) fn measure_wrap_storage_usage(&mut self) -> StorageUsage {
+        let initial_storage_usage = env::storage_usage();
+        let tmp_account_id = AccountId::new_unchecked("a".repeat(64));
+        self.wrapped_balances.insert(&tmp_account_id, &0);
+        let storage_usage = env::storage_usage() - initial_storage_usage;
+        self.wrapped_balances.remove(&tmp_account_id);
+        storage_usage
+    }
+
+    /// Drops the wrapped balance of a closed account. Its tokens are disposed of with the rest
+    /// of the balance, so the NEAR backing them is no longer owed.
+    pub(crate) fn release_wrapped_balance(&mut self, account_id: &AccountId) {
+        if let Some(wrapped) = self.wrapped_balances.remove(account_id) {
+            self.wrapped_near -= self.tokens_to_near(wrapped);
+        }
+    }
+
+    /// Tokens minted for `amount` yoctoNEAR, scaled from NEAR's 24 decimals to the token's.
+    fn near_to_tokens(&self, amount: Balance) -> Balance {
+        mul_div(amount, self.token_unit(), ONE_NEAR)
+    }
+
+    fn tokens_to_near(&self, amount: Balance) -> Balance {
+        mul_div(amount, ONE_NEAR, self.token_unit())
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::testing_env;
+
+    use super::*;
+    use crate::tests::get_context;
+
+    /// Token units in one whole token at the default decimals.
+    const ONE_TOKEN: Balance = 10u128.pow(DEFAULT_DECIMALS as u32);
+
+    fn setup(ctx: &mut VMContextBuilder) -> Contract {
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx.attached_deposit(1).build());
+        contract.set_wrap_enabled(true);
+        contract
+    }
+
+    #[test]
+    fn test_deposit_registers_and_withdraw_burns() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let storage_cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(storage_cost + ONE_NEAR)
+            .build());
+        assert_eq!(contract.near_deposit(), U128(ONE_TOKEN));
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, ONE_TOKEN);
+        assert_eq!(contract.wrapped_balance_of(accounts(2)).0, ONE_TOKEN);
+        assert!(contract.storage_balance_of(accounts(2)).is_some());
+        assert_eq!(
+            contract.ft_total_supply().0,
+            DEFAULT_TOTAL_SUPPLY + ONE_TOKEN
+        );
+
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .build());
+        contract.near_withdraw(U128(ONE_TOKEN / 4));
+        assert_eq!(
+            get_logs(),
+            vec![format!(
+                r#"EVENT_JSON:{{"standard":"nep141","version":"1.0.0","event":"ft_burn","data":[{{"owner_id":"{}","amount":"{}","memo":"near_withdraw"}}]}}"#,
+                accounts(2),
+                ONE_TOKEN / 4
+            )]
+        );
+        assert_eq!(contract.ft_balance_of(accounts(2)).0, ONE_TOKEN * 3 / 4);
+        assert_eq!(
+            contract.wrapped_balance_of(accounts(2)).0,
+            ONE_TOKEN * 3 / 4
+        );
+        assert_eq!(
+            contract.wrap_info(),
+            WrapInfo {
+                enabled: true,
+                wrapped_near: U128(ONE_NEAR * 3 / 4),
+            }
+        );
+        assert_eq!(
+            contract.ft_total_supply().0,
+            DEFAULT_TOTAL_SUPPLY + ONE_TOKEN * 3 / 4
+        );
+        assert_eq!(contract.ft_total_burned().0, ONE_TOKEN / 4);
+    }
+
+    #[test]
+    fn test_deposit_below_token_unit_is_refunded() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let yocto_per_unit = ONE_NEAR / ONE_TOKEN;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(ONE_NEAR + yocto_per_unit - 1)
+            .build());
+        assert_eq!(contract.near_deposit(), U128(ONE_TOKEN));
+        assert_eq!(contract.wrap_info().wrapped_near, U128(ONE_NEAR));
+    }
+
+    #[test]
+    #[should_panic(expected = "The amount exceeds the wrapped balance of the account")]
+    fn test_withdraw_is_limited_to_wrapped_balance() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let storage_cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(storage_cost + ONE_NEAR)
+            .build());
+        contract.near_deposit();
+        // The owner holds plenty of tokens, but wrapped no NEAR of its own.
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(1))
+            .attached_deposit(1)
+            .build());
+        contract.near_withdraw(U128(ONE_TOKEN));
+    }
+
+    #[test]
+    #[should_panic(expected = "E003: The account doesn't have enough balance")]
+    fn test_withdraw_of_locked_tokens() {
+        let ctx = &mut get_context(accounts(1));
+        let mut contract = setup(ctx);
+        let storage_cost = contract.storage_balance_bounds().min.0;
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(storage_cost + ONE_NEAR)
+            .build());
+        contract.near_deposit();
+        testing_env!(ctx
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .build());
+        contract.lock(U128(ONE_TOKEN / 2), 60);
+        contract.near_withdraw(U128(ONE_TOKEN));
+    }
+
+    #[test]
+    #[should_panic(expected = "Wrapping NEAR is disabled")]
+    fn test_deposit_when_disabled() {
+        let ctx = &mut get_context(accounts(1));
+        testing_env!(ctx.build());
+        let mut contract = Contract::new_default_config(accounts(1), accounts(3));
+        testing_env!(ctx.attached_deposit(ONE_NEAR).build());
+        contract.near_deposit();
+    }
+}
@@ -1,26 +1,75 @@
 /*!
-Some hypothetical DeFi contract that will do smart things with the transferred tokens
+A DeFi contract receiving MUTE through `ft_transfer_call`.
+
+The `msg` of the transfer is a JSON action:
+  - `{"action": "deposit"}` credits the sender's deposit.
+  - `{"action": "stake"}` credits the sender's stake.
+  - `{"action": "repay"}` pays back the sender's debt, the excess is returned to the token.
+
+MUTE charges its transfer fee before calling `ft_on_transfer`, so positions are credited with
+the `amount` passed to it, which is the net amount the contract actually received. Withdrawals
+and loans are sent back with `ft_transfer` and are taxed again on the way out.
 */
+use near_contract_standards::fungible_token::core::ext_ft_core;
 use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
 use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{
-    env, log, near_bindgen, require, AccountId, Balance, Gas, PanicOnDefault, PromiseOrValue,
+    assert_one_yocto, env, log, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas,
+    PanicOnDefault, PromiseOrValue, PromiseResult, ONE_YOCTO,
 };
 
-const BASE_GAS: u64 = 5_000_000_000_000;
-const PROMISE_CALL: u64 = 5_000_000_000_000;
-const GAS_FOR_FT_ON_TRANSFER: Gas = Gas(BASE_GAS + PROMISE_CALL);
+const GAS_FOR_FT_TRANSFER: Gas = Gas(10_000_000_000_000);
+const GAS_FOR_RESOLVE_PAYOUT: Gas = Gas(10_000_000_000_000);
+/// Loans can take up to half of the deposit.
+const MAX_LOAN_TO_DEPOSIT_BPS: u128 = 5_000;
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Positions,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Position {
+    pub deposited: Balance,
+    pub staked: Balance,
+    pub debt: Balance,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PositionView {
+    pub deposited: U128,
+    pub staked: U128,
+    pub debt: U128,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum TransferAction {
+    Deposit,
+    Stake,
+    Repay,
+}
+
+/// Which part of a position a payout came from, so it can be restored if the transfer fails.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(rename_all = "snake_case")]
+pub enum Payout {
+    Withdraw,
+    Unstake,
+    Borrow,
+}
 
 #[near_bindgen]
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct DeFi {
     fungible_token_account_id: AccountId,
-}
-
-// Have to repeat the same trait for our own implementation.
-trait ValueReturnTrait {
-    fn value_please(&self, amount_to_return: String) -> PromiseOrValue<U128>;
+    positions: LookupMap<AccountId, Position>,
 }
 
 #[near_bindgen]
@@ -29,16 +78,110 @@ impl DeFi {
     pub fn new(fungible_token_account_id: AccountId) -> Self {
         require!(!env::state_exists(), "Already initialized");
         Self {
-            fungible_token_account_id: fungible_token_account_id.into(),
+            fungible_token_account_id,
+            positions: LookupMap::new(StorageKey::Positions),
+        }
+    }
+
+    pub fn position_of(&self, account_id: AccountId) -> PositionView {
+        let position = self.internal_position(&account_id);
+        PositionView {
+            deposited: position.deposited.into(),
+            staked: position.staked.into(),
+            debt: position.debt.into(),
         }
     }
+
+    /// Sends `amount` of the predecessor's deposit back. The token fee is paid from it, so the
+    /// account receives less than `amount`.
+    #[payable]
+    pub fn withdraw(&mut self, amount: U128) -> PromiseOrValue<U128> {
+        self.payout(Payout::Withdraw, amount.0)
+    }
+
+    #[payable]
+    pub fn unstake(&mut self, amount: U128) -> PromiseOrValue<U128> {
+        self.payout(Payout::Unstake, amount.0)
+    }
+
+    /// Lends up to half of the predecessor's deposit.
+    #[payable]
+    pub fn borrow(&mut self, amount: U128) -> PromiseOrValue<U128> {
+        self.payout(Payout::Borrow, amount.0)
+    }
+
+    /// Restores the position if the token transfer failed. Returns the amount paid out.
+    #[private]
+    pub fn resolve_payout(&mut self, account_id: AccountId, payout: Payout, amount: U128) -> U128 {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return amount;
+        }
+        let mut position = self.internal_position(&account_id);
+        match payout {
+            Payout::Withdraw => position.deposited += amount.0,
+            Payout::Unstake => position.staked += amount.0,
+            Payout::Borrow => position.debt -= amount.0,
+        }
+        self.positions.insert(&account_id, &position);
+        log!("Payout of {} to @{} failed", amount.0, account_id);
+        U128(0)
+    }
+}
+
+impl DeFi {
+    fn internal_position(&self, account_id: &AccountId) -> Position {
+        self.positions.get(account_id).unwrap_or_default()
+    }
+
+    fn payout(&mut self, payout: Payout, amount: Balance) -> PromiseOrValue<U128> {
+        assert_one_yocto();
+        require!(amount > 0, "The amount should be a positive number");
+        let account_id = env::predecessor_account_id();
+        let mut position = self.internal_position(&account_id);
+        match payout {
+            Payout::Withdraw => {
+                require!(position.deposited >= amount, "Not enough deposited");
+                position.deposited -= amount;
+                require!(
+                    position.debt <= max_loan(position.deposited),
+                    "The deposit backs a loan"
+                );
+            }
+            Payout::Unstake => {
+                require!(position.staked >= amount, "Not enough staked");
+                position.staked -= amount;
+            }
+            Payout::Borrow => {
+                position.debt += amount;
+                require!(
+                    position.debt <= max_loan(position.deposited),
+                    "Not enough deposit for this loan"
+                );
+            }
+        }
+        self.positions.insert(&account_id, &position);
+
+        ext_ft_core::ext(self.fungible_token_account_id.clone())
+            .with_attached_deposit(ONE_YOCTO)
+            .with_static_gas(GAS_FOR_FT_TRANSFER)
+            .ft_transfer(account_id.clone(), amount.into(), None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(GAS_FOR_RESOLVE_PAYOUT)
+                    .resolve_payout(account_id, payout, amount.into()),
+            )
+            .into()
+    }
+}
+
+fn max_loan(deposited: Balance) -> Balance {
+    deposited * MAX_LOAN_TO_DEPOSIT_BPS / 10_000
 }
 
 #[near_bindgen]
 impl FungibleTokenReceiver for DeFi {
-    /// If given `msg: "take-my-money", immediately returns U128::From(0)
-    /// Otherwise, makes a cross-contract call to own `value_please` function, passing `msg`
-    /// value_please will attempt to parse `msg` as an integer and return a U128 version of it
+    /// Applies the JSON action in `msg` to the sender's position and returns the unused amount.
+    /// Invalid messages panic, so the whole transfer is refunded.
     fn ft_on_transfer(
         &mut self,
         sender_id: AccountId,
@@ -50,31 +193,346 @@ impl FungibleTokenReceiver for DeFi {
             env::predecessor_account_id() == self.fungible_token_account_id,
             "Only supports the one fungible token contract"
         );
+        let action: TransferAction = near_sdk::serde_json::from_str(&msg)
+            .unwrap_or_else(|_| env::panic_str("Invalid transfer message"));
         log!(
-            "in {} tokens from @{} ft_on_transfer, msg = {}",
+            "in {} tokens from @{} ft_on_transfer, action = {:?}",
             amount.0,
-            sender_id.as_ref(),
-            msg
+            sender_id,
+            action
         );
-        match msg.as_str() {
-            "take-my-money" => PromiseOrValue::Value(U128::from(0)),
-            _ => {
-                let prepaid_gas = env::prepaid_gas();
-                let account_id = env::current_account_id();
-                Self::ext(account_id)
-                    .with_static_gas(prepaid_gas - GAS_FOR_FT_ON_TRANSFER)
-                    .value_please(msg)
-                    .into()
+        let mut position = self.internal_position(&sender_id);
+        let unused = match action {
+            TransferAction::Deposit => {
+                position.deposited += amount.0;
+                0
             }
-        }
+            TransferAction::Stake => {
+                position.staked += amount.0;
+                0
+            }
+            TransferAction::Repay => {
+                require!(position.debt > 0, "Nothing to repay");
+                let repaid = std::cmp::min(position.debt, amount.0);
+                position.debt -= repaid;
+                amount.0 - repaid
+            }
+        };
+        self.positions.insert(&sender_id, &position);
+        PromiseOrValue::Value(unused.into())
     }
 }
 
-#[near_bindgen]
-impl ValueReturnTrait for DeFi {
-    fn value_please(&self, amount_to_return: String) -> PromiseOrValue<U128> {
-        log!("in value_please, amount_to_return = {}", amount_to_return);
-        let amount: Balance = amount_to_return.parse().expect("Not an integer");
-        PromiseOrValue::Value(amount.into())
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::collections::HashMap;
+
+    use fungible_token::Contract as Token;
+    use near_contract_standards::fungible_token::core::FungibleTokenCore;
+    use near_contract_standards::storage_management::StorageManagement;
+    use near_sdk::mock::with_mocked_blockchain;
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, MockedBlockchain, RuntimeFeesConfig, VMConfig};
+
+    use super::*;
+
+    fn setup(ctx: &mut VMContextBuilder) -> DeFi {
+        ctx.current_account_id(accounts(0))
+            .predecessor_account_id(accounts(1));
+        testing_env!(ctx.build());
+        DeFi::new(accounts(1))
+    }
+
+    fn on_transfer(contract: &mut DeFi, amount: Balance, msg: &str) -> Balance {
+        match contract.ft_on_transfer(accounts(2), amount.into(), msg.into()) {
+            PromiseOrValue::Value(unused) => unused.0,
+            PromiseOrValue::Promise(_) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_actions_credit_received_amount() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        // 100 were sent, MUTE kept 10 as the fee and passes the 90 received.
+        assert_eq!(
+            on_transfer(&mut contract, 90, r#"{"action": "deposit"}"#),
+            0
+        );
+        assert_eq!(on_transfer(&mut contract, 45, r#"{"action": "stake"}"#), 0);
+        assert_eq!(
+            contract.position_of(accounts(2)),
+            PositionView {
+                deposited: U128(90),
+                staked: U128(45),
+                debt: U128(0),
+            }
+        );
+    }
+
+    #[test]
+    fn test_borrow_and_repay_with_excess() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        on_transfer(&mut contract, 1_000, r#"{"action": "deposit"}"#);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(1)
+            .build());
+        contract.borrow(U128(500));
+        assert_eq!(contract.position_of(accounts(2)).debt, U128(500));
+
+        testing_env!(ctx.predecessor_account_id(accounts(1)).build());
+        assert_eq!(on_transfer(&mut contract, 450, r#"{"action": "repay"}"#), 0);
+        // Only the remaining 50 are used, the rest goes back to the sender.
+        assert_eq!(on_transfer(&mut contract, 80, r#"{"action": "repay"}"#), 30);
+        assert_eq!(contract.position_of(accounts(2)).debt, U128(0));
+    }
+
+    #[test]
+    fn test_failed_withdrawal_is_restored() {
+        let ctx = &mut VMContextBuilder::new();
+        let mut contract = setup(ctx);
+        on_transfer(&mut contract, 1_000, r#"{"action": "deposit"}"#);
+        testing_env!(ctx
+            .predecessor_account_id(accounts(2))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw(U128(400));
+        assert_eq!(contract.position_of(accounts(2)).deposited, U128(600));
+
+        testing_env!(
+            ctx.predecessor_account_id(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        assert_eq!(
+            contract.resolve_payout(accounts(2), Payout::Withdraw, U128(400)),
+            U128(0)
+        );
+        assert_eq!(contract.position_of(accounts(2)).deposited, U128(1_000));
+    }
+
+    /// Runs the token and the DeFi contract side by side. Each contract keeps its own storage,
+    /// since both put their first collection under the same prefix.
+    struct Chain {
+        ctx: VMContextBuilder,
+        storages: HashMap<AccountId, HashMap<Vec<u8>, Vec<u8>>>,
+        current: Option<AccountId>,
+        token: Token,
+        defi: DeFi,
+    }
+
+    fn token_id() -> AccountId {
+        "mute.near".parse().unwrap()
+    }
+
+    fn defi_id() -> AccountId {
+        "defi.near".parse().unwrap()
+    }
+
+    impl Chain {
+        /// The owner sends 10_000 to the user, who gets 9_000 after the 10% fee.
+        fn new() -> Self {
+            let mut ctx = VMContextBuilder::new();
+            ctx.current_account_id(token_id())
+                .predecessor_account_id(accounts(1));
+            testing_env!(ctx.build());
+            let token = Token::new_default_config(accounts(1), accounts(3));
+            let storages =
+                HashMap::from([(token_id(), with_mocked_blockchain(|b| b.take_storage()))]);
+            ctx.current_account_id(defi_id());
+            testing_env!(ctx.build());
+            let defi = DeFi::new(token_id());
+            let mut chain = Self {
+                ctx,
+                storages,
+                current: Some(defi_id()),
+                token,
+                defi,
+            };
+            for account_id in [defi_id(), accounts(2)] {
+                let deposit = chain.token.storage_balance_bounds().min.0;
+                chain.call_token(account_id.clone(), deposit, vec![]);
+                chain.token.storage_deposit(Some(account_id), None);
+            }
+            chain.call_token(accounts(1), 1, vec![]);
+            chain.token.enable_trading(None, None);
+            chain.token.ft_transfer(accounts(2), U128(10_000), None);
+            assert_eq!(chain.token.ft_balance_of(accounts(2)), U128(9_000));
+            chain
+        }
+
+        fn switch(
+            &mut self,
+            contract_id: AccountId,
+            predecessor_id: AccountId,
+            deposit: Balance,
+            promise_results: Vec<PromiseResult>,
+        ) {
+            let storage = with_mocked_blockchain(|b| b.take_storage());
+            if let Some(current) = self.current.replace(contract_id.clone()) {
+                self.storages.insert(current, storage);
+            }
+            let storage = self.storages.remove(&contract_id).unwrap_or_default();
+            let context = self
+                .ctx
+                .current_account_id(contract_id)
+                .predecessor_account_id(predecessor_id)
+                .attached_deposit(deposit)
+                .build();
+            env::set_blockchain_interface(MockedBlockchain::new(
+                context,
+                VMConfig::test(),
+                RuntimeFeesConfig::test(),
+                promise_results,
+                storage,
+                Default::default(),
+                None,
+            ));
+            self.ctx.storage_usage(env::storage_usage());
+        }
+
+        fn call_token(
+            &mut self,
+            predecessor_id: AccountId,
+            deposit: Balance,
+            promise_results: Vec<PromiseResult>,
+        ) {
+            self.switch(token_id(), predecessor_id, deposit, promise_results);
+        }
+
+        fn call_defi(
+            &mut self,
+            predecessor_id: AccountId,
+            deposit: Balance,
+            promise_results: Vec<PromiseResult>,
+        ) {
+            self.switch(defi_id(), predecessor_id, deposit, promise_results);
+        }
+
+        /// `ft_transfer_call` from the user, through `ft_on_transfer` and back to the token's
+        /// resolve callback. Returns the amount the DeFi contract kept.
+        fn transfer_call(&mut self, amount: Balance, msg: &str) -> Balance {
+            self.call_token(accounts(2), 1, vec![]);
+            let quote = self
+                .token
+                .ft_quote_transfer(accounts(2), defi_id(), U128(amount), None);
+            self.token
+                .ft_transfer_call(defi_id(), U128(amount), None, msg.into());
+
+            let received = quote.received.0;
+            self.call_defi(token_id(), 0, vec![]);
+            let unused = match self
+                .defi
+                .ft_on_transfer(accounts(2), U128(received), msg.into())
+            {
+                PromiseOrValue::Value(unused) => unused,
+                PromiseOrValue::Promise(_) => unreachable!(),
+            };
+
+            let result = near_sdk::serde_json::to_vec(&unused).unwrap();
+            self.call_token(token_id(), 0, vec![PromiseResult::Successful(result)]);
+            self.token
+                .ft_resolve_transfer_with_fee(
+                    accounts(2),
+                    defi_id(),
+                    quote.received,
+                    quote.fee,
+                    None,
+                )
+                .0
+        }
+
+        /// A DeFi payout to the user, through the token's `ft_transfer` and back to
+        /// `resolve_payout`.
+        fn payout(&mut self, payout: Payout, amount: Balance) -> Balance {
+            self.call_defi(accounts(2), 1, vec![]);
+            match payout {
+                Payout::Withdraw => self.defi.withdraw(U128(amount)),
+                Payout::Unstake => self.defi.unstake(U128(amount)),
+                Payout::Borrow => self.defi.borrow(U128(amount)),
+            };
+
+            self.call_token(defi_id(), 1, vec![]);
+            self.token.ft_transfer(accounts(2), U128(amount), None);
+
+            self.call_defi(defi_id(), 0, vec![PromiseResult::Successful(vec![])]);
+            self.defi
+                .resolve_payout(accounts(2), payout, U128(amount))
+                .0
+        }
+
+        fn balance_of(&mut self, account_id: AccountId) -> Balance {
+            self.call_token(accounts(1), 0, vec![]);
+            self.token.ft_balance_of(account_id).0
+        }
+
+        fn position(&mut self) -> PositionView {
+            self.call_defi(accounts(1), 0, vec![]);
+            self.defi.position_of(accounts(2))
+        }
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_through_token() {
+        let mut chain = Chain::new();
+        // 2_000 sent, the token keeps a 200 fee and the deposit is the 1_800 received.
+        assert_eq!(
+            chain.transfer_call(2_000, r#"{"action": "deposit"}"#),
+            1_800
+        );
+        assert_eq!(chain.position().deposited, U128(1_800));
+        assert_eq!(chain.balance_of(defi_id()), 1_800);
+        assert_eq!(chain.balance_of(accounts(2)), 7_000);
+
+        // The withdrawal is taxed again, the account gets 900 of the 1_000 taken from the
+        // deposit.
+        assert_eq!(chain.payout(Payout::Withdraw, 1_000), 1_000);
+        assert_eq!(chain.position().deposited, U128(800));
+        assert_eq!(chain.balance_of(defi_id()), 800);
+        assert_eq!(chain.balance_of(accounts(2)), 7_900);
+        assert_eq!(chain.token.ft_accrued_fees(), U128(1_000 + 200 + 100));
+    }
+
+    #[test]
+    fn test_repay_excess_is_refunded_by_token() {
+        let mut chain = Chain::new();
+        chain.transfer_call(2_000, r#"{"action": "deposit"}"#);
+        assert_eq!(chain.payout(Payout::Borrow, 500), 500);
+        assert_eq!(chain.balance_of(accounts(2)), 7_000 + 450);
+
+        // 900 of the 1_000 sent reach the contract, 400 of them are more than the debt and go
+        // back to the user. The fee is paid on the whole transfer.
+        assert_eq!(chain.transfer_call(1_000, r#"{"action": "repay"}"#), 500);
+        assert_eq!(chain.position().debt, U128(0));
+        assert_eq!(chain.balance_of(defi_id()), 1_800);
+        assert_eq!(chain.balance_of(accounts(2)), 7_450 - 1_000 + 400);
+        assert_eq!(chain.token.ft_accrued_fees(), U128(1_000 + 200 + 50 + 100));
+    }
+
+    #[test]
+    fn test_rejected_message_is_refunded_by_token() {
+        let mut chain = Chain::new();
+        chain.call_token(accounts(2), 1, vec![]);
+        chain
+            .token
+            .ft_transfer_call(defi_id(), U128(1_000), None, "take-my-money".into());
+        // `ft_on_transfer` panics on the invalid message, no fee is charged.
+        chain.call_token(token_id(), 0, vec![PromiseResult::Failed]);
+        assert_eq!(
+            chain.token.ft_resolve_transfer_with_fee(
+                accounts(2),
+                defi_id(),
+                U128(900),
+                U128(100),
+                None
+            ),
+            U128(0)
+        );
+        assert_eq!(chain.balance_of(accounts(2)), 9_000);
+        assert_eq!(chain.balance_of(defi_id()), 0);
     }
 }
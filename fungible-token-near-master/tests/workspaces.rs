@@ -1,4 +1,6 @@
+use defi::PositionView;
 use fungible_token::{DEFAULT_TOTAL_SUPPLY, TRANSFER_FEE_BPS};
+use mute_client::{ContractError, MuteError};
 use near_sdk::json_types::U128;
 use near_sdk::ONE_YOCTO;
 use near_units::parse_near;
@@ -18,7 +20,7 @@ async fn register_user(
         .call(&worker, "storage_deposit")
         .args_json((account_id, Option::<bool>::None))?
         .gas(300_000_000_000_000)
-        .deposit(near_sdk::env::storage_byte_cost() * 125)
+        .deposit(parse_near!("0.01 N"))
         .transact()
         .await?;
     assert!(res.is_success());
@@ -26,6 +28,27 @@ async fn register_user(
     Ok(())
 }
 
+async fn position_of(
+    worker: &Worker<impl Network>,
+    defi_contract: &Contract,
+    account_id: &AccountId,
+) -> anyhow::Result<PositionView> {
+    Ok(defi_contract
+        .call(&worker, "position_of")
+        .args_json((account_id,))?
+        .view()
+        .await?
+        .json::<PositionView>()?)
+}
+
+async fn accrued_fees(worker: &Worker<impl Network>, contract: &Contract) -> anyhow::Result<U128> {
+    Ok(contract
+        .call(&worker, "ft_accrued_fees")
+        .view()
+        .await?
+        .json::<U128>()?)
+}
+
 async fn init(
     worker: &Worker<impl DevNetwork>,
 ) -> anyhow::Result<(Contract, Account, Account, Contract)> {
@@ -74,7 +97,7 @@ async fn init(
         .call(&worker, "storage_deposit")
         .args_json((alice.id(), Option::<bool>::None))?
         .gas(300_000_000_000_000)
-        .deposit(near_sdk::env::storage_byte_cost() * 125)
+        .deposit(parse_near!("0.01 N"))
         .transact()
         .await?;
     assert!(res.is_success());
@@ -123,15 +146,29 @@ async fn test_simple_transfer() -> anyhow::Result<()> {
         .view()
         .await?
         .json::<U128>()?;
+    let accrued_fees = contract
+        .call(&worker, "ft_accrued_fees")
+        .view()
+        .await?
+        .json::<U128>()?;
+    assert_eq!(initial_balance.0 - transfer_amount.0, root_balance.0);
+    assert_eq!(expected_fee.0, accrued_fees.0);
+    assert_eq!(transfer_amount.0 - expected_fee.0, alice_balance.0);
+
+    let res = fee_receiver
+        .call(&worker, contract.id(), "claim_fees")
+        .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
     let fee_receiver_balance = contract
         .call(&worker, "ft_balance_of")
         .args_json((fee_receiver.id(),))?
         .view()
         .await?
         .json::<U128>()?;
-    assert_eq!(initial_balance.0 - transfer_amount.0, root_balance.0);
     assert_eq!(expected_fee.0, fee_receiver_balance.0);
-    assert_eq!(transfer_amount.0 - expected_fee.0, alice_balance.0);
 
     Ok(())
 }
@@ -165,8 +202,10 @@ async fn test_close_account_non_empty_balance() -> anyhow::Result<()> {
         .deposit(ONE_YOCTO)
         .transact()
         .await;
-    assert!(format!("{:?}", res)
-        .contains("Can't unregister the account with the positive balance without force"));
+    assert_eq!(
+        ContractError::from_panic_message(&format!("{:?}", res)).map(|e| e.error),
+        Some(MuteError::PositiveBalanceWithoutForce)
+    );
 
     let res = contract
         .call(&worker, "storage_unregister")
@@ -175,8 +214,10 @@ async fn test_close_account_non_empty_balance() -> anyhow::Result<()> {
         .deposit(ONE_YOCTO)
         .transact()
         .await;
-    assert!(format!("{:?}", res)
-        .contains("Can't unregister the account with the positive balance without force"));
+    assert_eq!(
+        ContractError::from_panic_message(&format!("{:?}", res)).map(|e| e.error),
+        Some(MuteError::PositiveBalanceWithoutForce)
+    );
 
     Ok(())
 }
@@ -206,7 +247,7 @@ async fn simulate_transfer_call_with_burned_amount() -> anyhow::Result<()> {
     let transfer_amount = U128::from(parse_near!("100 μN"));
     let expected_fee = calculate_fee(transfer_amount);
     let worker = workspaces::sandbox().await?;
-    let (contract, fee_receiver, _, defi_contract) = init(&worker).await?;
+    let (contract, _, _, defi_contract) = init(&worker).await?;
 
     // defi contract must be registered as a FT account
     register_user(&worker, &contract, defi_contract.id()).await?;
@@ -219,7 +260,7 @@ async fn simulate_transfer_call_with_burned_amount() -> anyhow::Result<()> {
             defi_contract.id(),
             transfer_amount,
             Option::<String>::None,
-            "10",
+            r#"{"action": "deposit"}"#,
         ))?
         .gas(300_000_000_000_000)
         .deposit(ONE_YOCTO)
@@ -248,22 +289,17 @@ async fn simulate_transfer_call_with_burned_amount() -> anyhow::Result<()> {
     // // to the sender, but was taken out of the receiver's balance and was burned.
     // assert_eq!(used_amount.0, transfer_amount);
 
+    // The rest of the root balance was burned, the deposit and the accrued fee remain.
     let res = contract.call(&worker, "ft_total_supply").view().await?;
-    assert_eq!(res.json::<U128>()?.0, transfer_amount.0 - 10);
+    assert_eq!(res.json::<U128>()?.0, transfer_amount.0);
     let defi_balance = contract
         .call(&worker, "ft_balance_of")
         .args_json((defi_contract.id(),))?
         .view()
         .await?
         .json::<U128>()?;
-    let fee_receiver_balance = contract
-        .call(&worker, "ft_balance_of")
-        .args_json((fee_receiver.id(),))?
-        .view()
-        .await?
-        .json::<U128>()?;
-    assert_eq!(defi_balance.0, transfer_amount.0 - expected_fee.0 - 10);
-    assert_eq!(fee_receiver_balance.0, expected_fee.0);
+    assert_eq!(defi_balance.0, transfer_amount.0 - expected_fee.0);
+    assert_eq!(accrued_fees(&worker, &contract).await?, expected_fee);
 
     Ok(())
 }
@@ -274,7 +310,7 @@ async fn simulate_transfer_call_with_immediate_return_and_no_refund() -> anyhow:
     let transfer_amount = U128::from(parse_near!("100 μN"));
     let expected_fee = calculate_fee(transfer_amount);
     let worker = workspaces::sandbox().await?;
-    let (contract, fee_receiver, _, defi_contract) = init(&worker).await?;
+    let (contract, _, _, defi_contract) = init(&worker).await?;
 
     // defi contract must be registered as a FT account
     register_user(&worker, &contract, defi_contract.id()).await?;
@@ -286,7 +322,7 @@ async fn simulate_transfer_call_with_immediate_return_and_no_refund() -> anyhow:
             defi_contract.id(),
             transfer_amount,
             Option::<String>::None,
-            "take-my-money",
+            r#"{"action": "deposit"}"#,
         ))?
         .gas(300_000_000_000_000)
         .deposit(ONE_YOCTO)
@@ -306,15 +342,12 @@ async fn simulate_transfer_call_with_immediate_return_and_no_refund() -> anyhow:
         .view()
         .await?
         .json::<U128>()?;
-    let fee_receiver_balance = contract
-        .call(&worker, "ft_balance_of")
-        .args_json((fee_receiver.id(),))?
-        .view()
-        .await?
-        .json::<U128>()?;
     assert_eq!(initial_balance.0 - transfer_amount.0, root_balance.0);
     assert_eq!(transfer_amount.0 - expected_fee.0, defi_balance.0);
-    assert_eq!(expected_fee.0, fee_receiver_balance.0);
+    assert_eq!(expected_fee, accrued_fees(&worker, &contract).await?);
+    // The deposit is credited with what defi received after the fee.
+    let position = position_of(&worker, &defi_contract, contract.id()).await?;
+    assert_eq!(position.deposited, defi_balance);
 
     Ok(())
 }
@@ -334,7 +367,7 @@ async fn simulate_transfer_call_when_called_contract_not_registered_with_ft() ->
             defi_contract.id(),
             transfer_amount,
             Option::<String>::None,
-            "take-my-money",
+            r#"{"action": "deposit"}"#,
         ))?
         .gas(300_000_000_000_000)
         .deposit(ONE_YOCTO)
@@ -362,15 +395,14 @@ async fn simulate_transfer_call_when_called_contract_not_registered_with_ft() ->
 }
 
 #[tokio::test]
-async fn simulate_transfer_call_with_promise_and_refund() -> anyhow::Result<()> {
+async fn simulate_withdraw_pays_the_fee_again() -> anyhow::Result<()> {
     let initial_balance = U128::from(DEFAULT_TOTAL_SUPPLY);
-    let refund_amount = U128::from(parse_near!("50 μN"));
     let transfer_amount = U128::from(parse_near!("100 μN"));
     let expected_fee = calculate_fee(transfer_amount);
+    let deposited = U128::from(transfer_amount.0 - expected_fee.0);
+    let withdraw_fee = calculate_fee(deposited);
     let worker = workspaces::sandbox().await?;
-    let (contract, fee_receiver, _, defi_contract) = init(&worker).await?;
-
-    // defi contract must be registered as a FT account
+    let (contract, _, _, defi_contract) = init(&worker).await?;
     register_user(&worker, &contract, defi_contract.id()).await?;
 
     let res = contract
@@ -379,13 +411,27 @@ async fn simulate_transfer_call_with_promise_and_refund() -> anyhow::Result<()>
             defi_contract.id(),
             transfer_amount,
             Option::<String>::None,
-            refund_amount.0.to_string(),
+            r#"{"action": "deposit"}"#,
         ))?
         .gas(300_000_000_000_000)
         .deposit(ONE_YOCTO)
         .transact()
         .await?;
     assert!(res.is_success());
+    let position = position_of(&worker, &defi_contract, contract.id()).await?;
+    assert_eq!(position.deposited, deposited);
+
+    // Withdrawing everything is another taxed transfer, from defi back to root.
+    let res = contract
+        .as_account()
+        .call(&worker, defi_contract.id(), "withdraw")
+        .args_json((deposited,))?
+        .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    assert_eq!(res.json::<U128>()?, deposited);
 
     let root_balance = contract
         .call(&worker, "ft_balance_of")
@@ -399,21 +445,91 @@ async fn simulate_transfer_call_with_promise_and_refund() -> anyhow::Result<()>
         .view()
         .await?
         .json::<U128>()?;
-    let fee_receiver_balance = contract
+    assert_eq!(
+        initial_balance.0 - expected_fee.0 - withdraw_fee.0,
+        root_balance.0
+    );
+    assert_eq!(0, defi_balance.0);
+    assert_eq!(
+        expected_fee.0 + withdraw_fee.0,
+        accrued_fees(&worker, &contract).await?.0
+    );
+    let position = position_of(&worker, &defi_contract, contract.id()).await?;
+    assert_eq!(position.deposited, U128(0));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn simulate_transfer_call_repay_and_refund() -> anyhow::Result<()> {
+    let initial_balance = U128::from(DEFAULT_TOTAL_SUPPLY);
+    let transfer_amount = U128::from(parse_near!("100 μN"));
+    let borrow_amount = U128::from(parse_near!("40 μN"));
+    let repay_amount = U128::from(parse_near!("50 μN"));
+    let expected_fee = calculate_fee(transfer_amount);
+    let borrow_fee = calculate_fee(borrow_amount);
+    let repay_fee = calculate_fee(repay_amount);
+    let worker = workspaces::sandbox().await?;
+    let (contract, _, _, defi_contract) = init(&worker).await?;
+    register_user(&worker, &contract, defi_contract.id()).await?;
+
+    let res = contract
+        .call(&worker, "ft_transfer_call")
+        .args_json((
+            defi_contract.id(),
+            transfer_amount,
+            Option::<String>::None,
+            r#"{"action": "deposit"}"#,
+        ))?
+        .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    let res = contract
+        .as_account()
+        .call(&worker, defi_contract.id(), "borrow")
+        .args_json((borrow_amount,))?
+        .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+    let res = contract
+        .call(&worker, "ft_transfer_call")
+        .args_json((
+            defi_contract.id(),
+            repay_amount,
+            Option::<String>::None,
+            r#"{"action": "repay"}"#,
+        ))?
+        .gas(300_000_000_000_000)
+        .deposit(ONE_YOCTO)
+        .transact()
+        .await?;
+    assert!(res.is_success());
+
+    // Only the borrowed amount of the received 45 μN was used, the rest was refunded.
+    let root_balance = contract
         .call(&worker, "ft_balance_of")
-        .args_json((fee_receiver.id(),))?
+        .args_json((contract.id(),))?
+        .view()
+        .await?
+        .json::<U128>()?;
+    let defi_balance = contract
+        .call(&worker, "ft_balance_of")
+        .args_json((defi_contract.id(),))?
         .view()
         .await?
         .json::<U128>()?;
     assert_eq!(
-        initial_balance.0 - transfer_amount.0 + refund_amount.0,
+        initial_balance.0 - transfer_amount.0 - borrow_fee.0 - repay_fee.0,
         root_balance.0
     );
-    assert_eq!(
-        transfer_amount.0 - expected_fee.0 - refund_amount.0,
-        defi_balance.0
-    );
-    assert_eq!(expected_fee.0, fee_receiver_balance.0);
+    assert_eq!(transfer_amount.0 - expected_fee.0, defi_balance.0);
+    let position = position_of(&worker, &defi_contract, contract.id()).await?;
+    assert_eq!(position.debt, U128(0));
+    assert_eq!(position.deposited, defi_balance);
 
     Ok(())
 }
@@ -435,7 +551,7 @@ async fn simulate_transfer_call_promise_panics_for_a_full_refund() -> anyhow::Re
             defi_contract.id(),
             transfer_amount,
             Option::<String>::None,
-            "no parsey as integer big panic oh no".to_string(),
+            "no parsey as json big panic oh no".to_string(),
         ))?
         .gas(300_000_000_000_000)
         .deposit(ONE_YOCTO)
@@ -450,7 +566,7 @@ async fn simulate_transfer_call_promise_panics_for_a_full_refund() -> anyhow::Re
     // if let ExecutionStatus::Failure(execution_error) =
     //     &res.promise_errors().remove(0).unwrap().outcome().status
     // {
-    //     assert!(execution_error.to_string().contains("ParseIntError"));
+    //     assert!(execution_error.to_string().contains("Invalid transfer message"));
     // } else {
     //     unreachable!();
     // }